@@ -1,12 +1,10 @@
-use chess;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use rust_chess;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use std::str::FromStr;
 use std::sync::mpsc;
 
 use rust_chess::config;
 use rust_chess::test_utils::setup_test_context;
-use rust_chess::threading::SearchGroup;
+use rust_chess::threading::ThreadPool;
 use rust_chess::uci::Position;
 
 fn startpos(c: &mut Criterion) {
@@ -14,7 +12,7 @@ fn startpos(c: &mut Criterion) {
 
     c.bench_function("startpos_d4", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(4))
         })
     });
@@ -25,7 +23,7 @@ fn startpos_1(c: &mut Criterion) {
 
     c.bench_function("startpos_d5", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(5))
         })
     });
@@ -36,7 +34,7 @@ fn startpos_2(c: &mut Criterion) {
 
     c.bench_function("startpos_d6", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(6))
         })
     });
@@ -47,7 +45,7 @@ fn startpos_3_single(c: &mut Criterion) {
 
     c.bench_function("startpos_d7_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(7))
         })
     });
@@ -58,22 +56,24 @@ fn startpos_3_parallel(c: &mut Criterion) {
 
     // TODO: change maybe move order to exploit table
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    // The threads are started once, every iteration clears the table
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("startpos_d7_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(7),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(7),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
@@ -85,7 +85,7 @@ fn custom(c: &mut Criterion) {
 
     c.bench_function("custom_d4", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(4))
         })
     });
@@ -99,7 +99,7 @@ fn chezzz(c: &mut Criterion) {
 
     c.bench_function("chezzz_d2", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(2))
         })
     });
@@ -111,7 +111,7 @@ fn mate_in_3_single(c: &mut Criterion) {
 
     c.bench_function("mate_in_three_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(6))
         })
     });
@@ -123,22 +123,23 @@ fn mate_in_3_parallel(c: &mut Criterion) {
 
     // TODO: change maybe move order to exploit table
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("mate_in_three_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(6),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(6),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
@@ -152,7 +153,7 @@ fn liberman(c: &mut Criterion) {
 
     c.bench_function("liberman_d1", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(1))
         })
     });
@@ -165,7 +166,7 @@ fn middlegame(c: &mut Criterion) {
 
     c.bench_function("middlegame_d5", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(5))
         })
     });
@@ -178,7 +179,7 @@ fn middlegame_1(c: &mut Criterion) {
 
     c.bench_function("middlegame_d6", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(6))
         })
     });
@@ -191,7 +192,7 @@ fn middlegame_2_single(c: &mut Criterion) {
 
     c.bench_function("middlegame_d7_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(7))
         })
     });
@@ -202,22 +203,23 @@ fn middlegame_2_parallel(c: &mut Criterion) {
         chess::Board::from_str("r4r1k/1pq1p1bp/1pnp2p1/p2B4/2PP2Q1/4B2P/PP3PP1/1R3RK1 w - - 6 20")
             .expect("Valid Board");
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("middlegame_d7_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(7),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(7),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
@@ -230,7 +232,7 @@ fn middlegame_3_single(c: &mut Criterion) {
 
     c.bench_function("middlegame_3_d7_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(7))
         })
     });
@@ -241,22 +243,23 @@ fn middlegame_3_parallel(c: &mut Criterion) {
         chess::Board::from_str("2kr2nr/pp3ppp/1q2p1b1/2b5/Q1PN4/4B3/PP2NPPP/R4RK1 b - - 4 14")
             .expect("Valid Board");
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("middlegame_3_d7_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(7),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(7),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
@@ -268,7 +271,7 @@ fn endgame(c: &mut Criterion) {
 
     c.bench_function("endgame_d8", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(8))
         })
     });
@@ -280,7 +283,7 @@ fn endgame_1(c: &mut Criterion) {
 
     c.bench_function("endgame_d10", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(10))
         })
     });
@@ -292,7 +295,7 @@ fn endgame_2_single(c: &mut Criterion) {
 
     c.bench_function("endgame_d11_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(11))
         })
     });
@@ -302,22 +305,23 @@ fn endgame_2_parallel(c: &mut Criterion) {
     let board =
         chess::Board::from_str("8/p7/3n2k1/4K1P1/1P6/6N1/P6p/8 b - - 3 51").expect("Valid Board");
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("endgame_d11_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(11),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(11),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
@@ -329,7 +333,7 @@ fn mate_in_7(c: &mut Criterion) {
 
     c.bench_function("mate_in_7_d5", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(5))
         })
     });
@@ -341,7 +345,7 @@ fn stalemate(c: &mut Criterion) {
 
     c.bench_function("stalemate_d7", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(7))
         })
     });
@@ -355,7 +359,7 @@ fn out_of_opening_single(c: &mut Criterion) {
 
     c.bench_function("out_of_opening_d7_single", |b| {
         b.iter(|| {
-            let mut context = setup_test_context(board.clone());
+            let mut context = setup_test_context(board);
             context.root_search(black_box(7))
         })
     });
@@ -367,29 +371,73 @@ fn out_of_opening_parallel(c: &mut Criterion) {
     )
     .expect("Valid Board");
 
-    let position = Position {
-        board: board,
-        hash_history: vec![],
-    };
+    let position = Position::new(board);
     let (info_sender, _) = mpsc::channel();
+    let mut pool = ThreadPool::new(config::BENCHMARK_THREAD_COUNT, config::HASH_TABLE_SIZE);
 
     c.bench_function("out_of_opening_d7_parallel", |b| {
         b.iter(|| {
-            let search_group = SearchGroup::start(
-                position.clone(),
-                config::BENCHMARK_THREAD_COUNT,
-                info_sender.clone(),
-                config::HASH_TABLE_SIZE,
-                black_box(7),
-                None,
-            );
+            pool.new_game();
+            let search_group = pool
+                .start(
+                    &position,
+                    info_sender.clone(),
+                    black_box(7),
+                    None,
+                    None,
+                    &[],
+                )
+                .expect("Valid search parameters");
             let _ = search_group.await_principal();
         })
     });
 }
 
+// Time to depth of the same search with 1 to BENCHMARK_THREAD_COUNT threads,
+// shows how well Lazy SMP scales
+fn time_to_depth(c: &mut Criterion) {
+    let board = chess::Board::from_str(
+        "r4rk1/1ppqbppp/p1npn1b1/P3p3/4P3/2PPNN1P/1PB2PP1/R1BQR1K1 b - - 0 15",
+    )
+    .expect("Valid Board");
+
+    let position = Position::new(board);
+    let (info_sender, _) = mpsc::channel();
+
+    let mut group = c.benchmark_group("time_to_depth_7");
+    group.sample_size(10);
+
+    let mut threads = 1;
+    while threads <= config::BENCHMARK_THREAD_COUNT {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                let mut pool = ThreadPool::new(threads, config::HASH_TABLE_SIZE);
+                b.iter(|| {
+                    pool.new_game();
+                    let search_group = pool
+                        .start(
+                            &position,
+                            info_sender.clone(),
+                            black_box(7),
+                            None,
+                            None,
+                            &[],
+                        )
+                        .expect("Valid search parameters");
+                    let _ = search_group.await_principal();
+                })
+            },
+        );
+        threads *= 2;
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
+    time_to_depth,
     out_of_opening_single,
     out_of_opening_parallel,
     middlegame_3_single,
@@ -25,7 +25,7 @@ pub fn sort_3<T: Ord>(mut vec: Vec<T>) -> Vec<T> {
 }
 
 pub fn sort_4(mut vec: Vec<u64>) -> Vec<u64> {
-    vec.sort_by_key(|n| std::u64::MAX - n);
+    vec.sort_by_key(|n| u64::MAX - n);
     vec
 }
 
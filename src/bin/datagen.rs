@@ -0,0 +1,156 @@
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use rust_chess::datagen::{Generator, GeneratorSettings};
+use rust_chess::nnue::Network;
+
+const USAGE: &str = "usage: datagen <output> [--format binary|text] [--games n] [--depth n] \
+[--nodes n] [--random-plies n] [--max-plies n] [--keep-checks] [--keep-captures] \
+[--network file] [--threads n] [--seed n]";
+const PROGRESS_INTERVAL: usize = 10;
+
+// Plays self-play games and writes their positions as training data for the
+// tuner or for a network, in the formats described in datagen.rs. The random
+// openings of a seed are always the same, whatever the number of threads.
+struct Arguments {
+    output: String,
+    text: bool,
+    games: usize,
+    threads: usize,
+    seed: u64,
+    settings: GeneratorSettings,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Arguments, String> {
+    let mut parsed = Arguments {
+        output: String::new(),
+        text: false,
+        games: 100,
+        threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+        seed: rand::random(),
+        settings: GeneratorSettings::default(),
+    };
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .cloned()
+                .ok_or(format!("missing value for {argument}"))
+        };
+        let invalid = |_| format!("invalid value for {argument}");
+        let settings = &mut parsed.settings;
+        match argument.as_str() {
+            "--format" => {
+                parsed.text = match value()?.as_str() {
+                    "binary" => false,
+                    "text" => true,
+                    format => return Err(format!("unknown format: {format}")),
+                }
+            }
+            "--games" => parsed.games = value()?.parse().map_err(invalid)?,
+            "--depth" => settings.depth = value()?.parse().map_err(invalid)?,
+            "--nodes" => settings.nodes = Some(value()?.parse().map_err(invalid)?),
+            "--random-plies" => settings.random_plies = value()?.parse().map_err(invalid)?,
+            "--max-plies" => settings.max_plies = value()?.parse().map_err(invalid)?,
+            "--keep-checks" => settings.skip_checks = false,
+            "--keep-captures" => settings.skip_captures = false,
+            "--network" => {
+                let path = value()?;
+                let network = Network::load(&path).map_err(|error| format!("{path}: {error}"))?;
+                settings.network = Some(Arc::new(network));
+            }
+            "--threads" => parsed.threads = value()?.parse().map_err(invalid)?,
+            "--seed" => parsed.seed = value()?.parse().map_err(invalid)?,
+            output if parsed.output.is_empty() && !output.starts_with("--") => {
+                parsed.output = output.to_string()
+            }
+            argument => return Err(format!("unexpected argument: {argument}")),
+        }
+    }
+
+    match parsed.output.is_empty() {
+        true => Err("missing output".to_string()),
+        false => Ok(parsed),
+    }
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let arguments = parse_arguments(&arguments).unwrap_or_else(|error| {
+        eprintln!("{error}\n{USAGE}");
+        process::exit(1);
+    });
+
+    let file = File::create(&arguments.output).unwrap_or_else(|error| {
+        eprintln!("{}: {error}", arguments.output);
+        process::exit(1);
+    });
+    let mut output = BufWriter::new(file);
+    println!("seed {}", arguments.seed);
+
+    // The workers take the games in turn, every game has its own random
+    // generator so that its opening only depends on the seed
+    let next_game = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<_> = (0..arguments.threads.max(1))
+        .map(|_| {
+            let next_game = next_game.clone();
+            let sender = sender.clone();
+            let settings = arguments.settings.clone();
+            let (games, seed) = (arguments.games, arguments.seed);
+            thread::spawn(move || {
+                let mut generator = Generator::new(settings);
+                loop {
+                    let game = next_game.fetch_add(1, Ordering::Relaxed);
+                    if game >= games {
+                        break;
+                    }
+                    let mut rng = StdRng::seed_from_u64(seed.wrapping_add(game as u64));
+                    if sender.send(generator.play_game(&mut rng)).is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    let (mut games, mut positions) = (0, 0);
+    for game in receiver {
+        for position in &game {
+            let written = match arguments.text {
+                true => writeln!(output, "{}", position.to_text()),
+                false => output.write_all(&position.pack()),
+            };
+            if let Err(error) = written {
+                eprintln!("{}: {error}", arguments.output);
+                process::exit(1);
+            }
+        }
+        games += 1;
+        positions += game.len();
+        if games % PROGRESS_INTERVAL == 0 {
+            println!("{games} games, {positions} positions");
+        }
+    }
+
+    for worker in workers {
+        let _ = worker.join();
+    }
+    if let Err(error) = output.flush() {
+        eprintln!("{}: {error}", arguments.output);
+        process::exit(1);
+    }
+    println!(
+        "{games} games, {positions} positions written to {}",
+        arguments.output
+    );
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process;
+
+use rust_chess::params::EvalParams;
+use rust_chess::tuner::{parse_labelled_position, Tuner};
+
+const USAGE: &str =
+    "usage: tune <corpus> [--output file] [--params file] [--passes n] [--threads n]";
+
+// Fits the evaluation parameters to a corpus of positions labelled with game
+// results and writes them as a JSON file that the engine reads with the UCI
+// option "EvalParams". The file is rewritten after every pass.
+struct Arguments {
+    corpus: String,
+    output: String,
+    params: Option<String>,
+    passes: usize,
+    threads: usize,
+}
+
+fn parse_arguments(arguments: &[String]) -> Result<Arguments, String> {
+    let mut parsed = Arguments {
+        corpus: String::new(),
+        output: "tuned.json".to_string(),
+        params: None,
+        passes: 100,
+        threads: std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+    };
+
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || {
+            arguments
+                .next()
+                .cloned()
+                .ok_or(format!("missing value for {argument}"))
+        };
+        match argument.as_str() {
+            "--output" => parsed.output = value()?,
+            "--params" => parsed.params = Some(value()?),
+            "--passes" => parsed.passes = value()?.parse().map_err(|_| "invalid passes")?,
+            "--threads" => parsed.threads = value()?.parse().map_err(|_| "invalid threads")?,
+            corpus if parsed.corpus.is_empty() && !corpus.starts_with("--") => {
+                parsed.corpus = corpus.to_string()
+            }
+            argument => return Err(format!("unexpected argument: {argument}")),
+        }
+    }
+
+    match parsed.corpus.is_empty() {
+        true => Err("missing corpus".to_string()),
+        false => Ok(parsed),
+    }
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let arguments = parse_arguments(&arguments).unwrap_or_else(|error| {
+        eprintln!("{error}\n{USAGE}");
+        process::exit(1);
+    });
+
+    let params = match &arguments.params {
+        Some(path) => EvalParams::load(path).unwrap_or_else(|error| {
+            eprintln!("{path}: {error}");
+            process::exit(1);
+        }),
+        None => EvalParams::default(),
+    };
+
+    let corpus = File::open(&arguments.corpus).unwrap_or_else(|error| {
+        eprintln!("{}: {error}", arguments.corpus);
+        process::exit(1);
+    });
+    let mut skipped = 0;
+    let positions: Vec<_> = BufReader::new(corpus)
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let position = parse_labelled_position(&line);
+            if position.is_none() {
+                skipped += 1;
+            }
+            position
+        })
+        .collect();
+    println!("{} positions, {skipped} lines skipped", positions.len());
+
+    let mut tuner = Tuner::new(positions, arguments.threads);
+    if tuner.is_empty() {
+        eprintln!("no labelled positions in {}", arguments.corpus);
+        process::exit(1);
+    }
+    let scaling = tuner.fit_scaling(&params);
+    println!("scaling {scaling:.4}, error {:.6}", tuner.error(&params));
+
+    let output = arguments.output.clone();
+    tuner.tune(&params, arguments.passes, |pass, error, tuned| {
+        println!("pass {pass}, error {error:.6}");
+        if let Err(error) = std::fs::write(&output, tuned.to_json()) {
+            eprintln!("{output}: {error}");
+        }
+    });
+    println!("parameters written to {output}");
+}
@@ -0,0 +1,341 @@
+use chess::{
+    BitBoard, Board, BoardBuilder, CastleRights, ChessMove, Color, File, Piece, Rank, Square,
+    ALL_COLORS, EMPTY,
+};
+
+// The chess crate only knows castling with the king on the e-file and the rooks
+// in the corners. For Chess960 the castling rights are kept here as the files of
+// the castling rooks, the board itself never has castle rights, and castling
+// moves are generated and played by the functions below.
+//
+// Castling moves use the king-takes-rook notation of the UCI_Chess960 mode,
+// e.g. "f1g1" castles with the rook on g1.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CastleSide {
+    King,
+    Queen,
+}
+
+const CASTLE_SIDES: [CastleSide; 2] = [CastleSide::King, CastleSide::Queen];
+
+// Zobrist keys of the castling rights, by color, side and file of the rook.
+// The board hash of the chess crate does not include them, as the board has
+// no castle rights in Chess960.
+const CASTLING_KEYS: [[[u64; 8]; 2]; 2] = castling_keys();
+
+const fn castling_keys() -> [[[u64; 8]; 2]; 2] {
+    // splitmix64, a fixed seed keeps the hashes the same in every run
+    let mut state: u64 = 0x3c6e_f372_fe94_f82b;
+    let mut keys = [[[0; 8]; 2]; 2];
+    let mut index = 0;
+    while index < 32 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut key = state;
+        key = (key ^ (key >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        key = (key ^ (key >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        keys[index / 16][index / 8 % 2][index % 8] = key ^ (key >> 31);
+        index += 1;
+    }
+    keys
+}
+
+impl CastleSide {
+    fn king_destination(&self) -> File {
+        match self {
+            CastleSide::King => File::G,
+            CastleSide::Queen => File::C,
+        }
+    }
+
+    fn rook_destination(&self) -> File {
+        match self {
+            CastleSide::King => File::F,
+            CastleSide::Queen => File::D,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CastlingRights {
+    rook_files: [[Option<File>; 2]; 2],
+}
+
+impl CastlingRights {
+    pub fn rook_file(&self, color: Color, side: CastleSide) -> Option<File> {
+        self.rook_files[color.to_index()][side as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rook_files.iter().flatten().all(|file| file.is_none())
+    }
+
+    // Combined with the board hash for repetitions and the transposition
+    // table, no rights hash to 0
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0;
+        for (color, sides) in self.rook_files.iter().enumerate() {
+            for (side, file) in sides.iter().enumerate() {
+                if let Some(file) = file {
+                    hash ^= CASTLING_KEYS[color][side][file.to_index()];
+                }
+            }
+        }
+        hash
+    }
+
+    // Parses the castling field of a FEN. Besides the standard "KQkq" this
+    // accepts Shredder-FEN, where the files of the castling rooks are given
+    // ("HAha"), and X-FEN, where "K" and "Q" refer to the outermost rook.
+    pub fn parse(field: &str, board: &Board) -> Option<CastlingRights> {
+        let mut rights = CastlingRights::default();
+        if field == "-" {
+            return Some(rights);
+        }
+
+        for character in field.chars() {
+            let color = match character.is_ascii_uppercase() {
+                true => Color::White,
+                false => Color::Black,
+            };
+            let back_rank = color.to_my_backrank();
+            let king_file = board.king_square(color).get_file();
+            if board.king_square(color).get_rank() != back_rank {
+                return None;
+            }
+
+            let rooks = board.pieces(Piece::Rook) & board.color_combined(color);
+            let rook_file = match character.to_ascii_lowercase() {
+                'k' => outermost_rook(board, color, CastleSide::King)?,
+                'q' => outermost_rook(board, color, CastleSide::Queen)?,
+                file @ 'a'..='h' => File::from_index(file as usize - 'a' as usize),
+                _ => return None,
+            };
+
+            if !has_rook(rooks, back_rank, rook_file) || rook_file == king_file {
+                return None;
+            }
+            let side = match rook_file.to_index() > king_file.to_index() {
+                true => CastleSide::King,
+                false => CastleSide::Queen,
+            };
+            rights.rook_files[color.to_index()][side as usize] = Some(rook_file);
+        }
+
+        Some(rights)
+    }
+
+    pub fn from_board(board: &Board) -> CastlingRights {
+        let mut rights = CastlingRights::default();
+        for color in ALL_COLORS {
+            let castle_rights = board.castle_rights(color);
+            if castle_rights.has_kingside() {
+                rights.rook_files[color.to_index()][CastleSide::King as usize] = Some(File::H);
+            }
+            if castle_rights.has_queenside() {
+                rights.rook_files[color.to_index()][CastleSide::Queen as usize] = Some(File::A);
+            }
+        }
+        rights
+    }
+
+    // The rights in the representation of the chess crate, only possible if
+    // they match a standard chess setup
+    pub fn to_standard(self, board: &Board) -> Option<[CastleRights; 2]> {
+        let mut standard = [CastleRights::NoRights; 2];
+
+        for color in ALL_COLORS {
+            let king_side = self.rook_file(color, CastleSide::King);
+            let queen_side = self.rook_file(color, CastleSide::Queen);
+            if king_side.is_none() && queen_side.is_none() {
+                continue;
+            }
+
+            let king_on_e_file =
+                board.king_square(color) == Square::make_square(color.to_my_backrank(), File::E);
+            let rooks_in_corners =
+                king_side.unwrap_or(File::H) == File::H && queen_side.unwrap_or(File::A) == File::A;
+            if !king_on_e_file || !rooks_in_corners {
+                return None;
+            }
+
+            standard[color.to_index()] = match (king_side, queen_side) {
+                (Some(_), Some(_)) => CastleRights::Both,
+                (Some(_), None) => CastleRights::KingSide,
+                _ => CastleRights::QueenSide,
+            };
+        }
+        Some(standard)
+    }
+
+    // Removes the rights that are lost when a piece moves from or to one of the
+    // given squares. A king move loses both rights of its color.
+    pub fn update(&mut self, board: &Board, chess_move: ChessMove) {
+        let mover = board.side_to_move();
+        if board.piece_on(chess_move.get_source()) == Some(Piece::King) {
+            self.rook_files[mover.to_index()] = [None; 2];
+        }
+
+        for square in [chess_move.get_source(), chess_move.get_dest()] {
+            for color in ALL_COLORS {
+                if square.get_rank() != color.to_my_backrank() {
+                    continue;
+                }
+                for file in self.rook_files[color.to_index()].iter_mut() {
+                    if *file == Some(square.get_file()) {
+                        *file = None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn to_fen_field(self, board: &Board) -> String {
+        let mut field = String::new();
+
+        for color in ALL_COLORS {
+            for side in CASTLE_SIDES {
+                if let Some(file) = self.rook_file(color, side) {
+                    let mut file_char = (b'a' + file.to_index() as u8) as char;
+                    if self.is_outermost_rook(board, color, side) {
+                        file_char = match side {
+                            CastleSide::King => 'k',
+                            CastleSide::Queen => 'q',
+                        };
+                    }
+                    match color {
+                        Color::White => field.push(file_char.to_ascii_uppercase()),
+                        Color::Black => field.push(file_char),
+                    }
+                }
+            }
+        }
+
+        match field.is_empty() {
+            true => "-".to_string(),
+            false => field,
+        }
+    }
+
+    fn is_outermost_rook(&self, board: &Board, color: Color, side: CastleSide) -> bool {
+        outermost_rook(board, color, side) == self.rook_file(color, side)
+    }
+}
+
+fn outermost_rook(board: &Board, color: Color, side: CastleSide) -> Option<File> {
+    let back_rank = color.to_my_backrank();
+    let rooks = board.pieces(Piece::Rook) & board.color_combined(color);
+    let king_file = board.king_square(color).get_file().to_index();
+
+    match side {
+        CastleSide::King => (king_file + 1..8)
+            .rev()
+            .map(File::from_index)
+            .find(|file| has_rook(rooks, back_rank, *file)),
+        CastleSide::Queen => (0..king_file)
+            .map(File::from_index)
+            .find(|file| has_rook(rooks, back_rank, *file)),
+    }
+}
+
+#[inline]
+fn has_rook(rooks: BitBoard, rank: Rank, file: File) -> bool {
+    rooks & BitBoard::from_square(Square::make_square(rank, file)) != EMPTY
+}
+
+// A castling move is a king move onto a square of its own castling rook
+pub fn castle_side(
+    board: &Board,
+    rights: &CastlingRights,
+    chess_move: ChessMove,
+) -> Option<CastleSide> {
+    let color = board.side_to_move();
+    if chess_move.get_source() != board.king_square(color)
+        || chess_move.get_dest().get_rank() != color.to_my_backrank()
+    {
+        return None;
+    }
+
+    CASTLE_SIDES
+        .into_iter()
+        .find(|side| rights.rook_file(color, *side) == Some(chess_move.get_dest().get_file()))
+}
+
+pub fn castling_moves(board: &Board, rights: &CastlingRights) -> Vec<(ChessMove, Board)> {
+    let color = board.side_to_move();
+    let mut moves = vec![];
+
+    for side in CASTLE_SIDES {
+        if let Some(rook_file) = rights.rook_file(color, side) {
+            if let Some(castled) = castle(board, color, side, rook_file) {
+                let rook_square = Square::make_square(color.to_my_backrank(), rook_file);
+                moves.push((
+                    ChessMove::new(board.king_square(color), rook_square, None),
+                    castled,
+                ));
+            }
+        }
+    }
+    moves
+}
+
+// Returns the board after castling, if castling is legal
+fn castle(board: &Board, color: Color, side: CastleSide, rook_file: File) -> Option<Board> {
+    let back_rank = color.to_my_backrank();
+    let king_square = board.king_square(color);
+    let rook_square = Square::make_square(back_rank, rook_file);
+    let king_destination = Square::make_square(back_rank, side.king_destination());
+    let rook_destination = Square::make_square(back_rank, side.rook_destination());
+
+    if board.piece_on(rook_square) != Some(Piece::Rook)
+        || board.color_on(rook_square) != Some(color)
+        || *board.checkers() != EMPTY
+    {
+        return None;
+    }
+
+    let occupied =
+        *board.combined() ^ BitBoard::from_square(king_square) ^ BitBoard::from_square(rook_square);
+    let king_path = squares_between_inclusive(king_square, king_destination);
+    let rook_path = squares_between_inclusive(rook_square, rook_destination);
+
+    if (king_path | rook_path) & occupied != EMPTY {
+        return None;
+    }
+    for square in king_path {
+        if is_attacked(board, square, !color, occupied) {
+            return None;
+        }
+    }
+
+    let mut builder = BoardBuilder::from(board);
+    builder
+        .clear_square(king_square)
+        .clear_square(rook_square)
+        .piece(king_destination, Piece::King, color)
+        .piece(rook_destination, Piece::Rook, color)
+        .side_to_move(!color)
+        .en_passant(None)
+        .castle_rights(Color::White, CastleRights::NoRights)
+        .castle_rights(Color::Black, CastleRights::NoRights);
+
+    // Board::try_from also rejects positions where the castling king is in check,
+    // e.g. from a piece that was behind the castling rook
+    Board::try_from(builder).ok()
+}
+
+fn squares_between_inclusive(from: Square, to: Square) -> BitBoard {
+    chess::between(from, to) | BitBoard::from_square(from) | BitBoard::from_square(to)
+}
+
+pub fn is_attacked(board: &Board, square: Square, attacker: Color, occupied: BitBoard) -> bool {
+    let pieces = board.color_combined(attacker);
+    let diagonal = (board.pieces(Piece::Bishop) | board.pieces(Piece::Queen)) & pieces;
+    let straight = (board.pieces(Piece::Rook) | board.pieces(Piece::Queen)) & pieces;
+
+    chess::get_bishop_moves(square, occupied) & diagonal != EMPTY
+        || chess::get_rook_moves(square, occupied) & straight != EMPTY
+        || chess::get_knight_moves(square) & board.pieces(Piece::Knight) & pieces != EMPTY
+        || chess::get_king_moves(square) & board.pieces(Piece::King) & pieces != EMPTY
+        || chess::get_pawn_attacks(square, !attacker, board.pieces(Piece::Pawn) & pieces) != EMPTY
+}
@@ -1,11 +1,18 @@
 use crate::search::SearchDepth;
 use chess::Piece;
 
+pub const ENGINE_NAME: &str = concat!("rust_chess ", env!("CARGO_PKG_VERSION"));
+pub const ENGINE_AUTHOR: &str = "Tobias Heinzle";
+
 pub const MAX_DEPTH: u8 = 64;
 pub const HASH_TABLE_SIZE: u32 = 1 << 22;
 pub const THREAD_COUNT: u8 = 8;
 pub const BENCHMARK_THREAD_COUNT: u8 = 8;
 
+// Time management
+pub const DEFAULT_MOVES_TO_GO: u32 = 30;
+pub const MOVE_OVERHEAD_MS: u64 = 50;
+
 // Evaluation constants
 pub const INFINITY: i32 = 1000000;
 pub const DRAW: i32 = 0;
@@ -33,3 +40,6 @@ pub const REP_TABLE_SIZE: usize = 1 << 16;
 
 // Search Extension
 pub const MAX_EXTENSION_PLIES: SearchDepth = 3;
+
+// How often a search checks its node and time limits
+pub const STOP_CHECK_NODES: u64 = 1024;
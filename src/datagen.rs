@@ -0,0 +1,354 @@
+use chess::{
+    BitBoard, Board, BoardBuilder, CastleRights, ChessMove, Color, File, Piece, ALL_PIECES, EMPTY,
+};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use crate::config;
+use crate::nnue::Network;
+use crate::position::Position;
+use crate::search::{PositionScore, SearchContext, SearchDepth};
+use crate::threading::new_hash_table;
+use crate::uci_command::is_convertible;
+
+// Training data from self-play. Every game starts with a few random moves and
+// is then played with fixed depth or node searches. The searched positions are
+// written with the search score, the ply and the result of the game.
+//
+// Binary record, 32 bytes, numbers little endian:
+//
+//   0..8    occupied squares         u64, a1 = bit 0
+//   8..24   pieces                   4 bits for every occupied square in the
+//                                    order of the squares, low nibble first:
+//                                    piece type (pawn 0 .. king 5) + 6 for black
+//   24..26  score                    i16, centipawns for the side to move
+//   26..28  ply                      u16, half moves since the start position
+//   28      result                   u8, 0 black won, 1 draw, 2 white won
+//   29      side to move             u8, 0 white, 1 black
+//   30      castle rights            u8, white in bits 0-1, black in bits 2-3:
+//                                    1 king side, 2 queen side
+//   31      en passant file          u8, 8 if there is none
+//
+// Text line, readable by the tuner:
+//
+//   <fen> | <score> | <ply> | <result as 1-0, 0-1 or 1/2-1/2>
+
+pub const RECORD_SIZE: usize = 32;
+const NO_EN_PASSANT: u8 = 8;
+// Openings that are already decided teach nothing
+const MAX_OPENING_SCORE: PositionScore = 400;
+const OPENING_CHECK_DEPTH: SearchDepth = 4;
+const TABLE_SIZE: u32 = 1 << 18;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    BlackWin,
+    Draw,
+    WhiteWin,
+}
+
+impl GameResult {
+    fn win_for(color: Color) -> GameResult {
+        match color {
+            Color::White => GameResult::WhiteWin,
+            Color::Black => GameResult::BlackWin,
+        }
+    }
+
+    pub fn to_text(self) -> &'static str {
+        match self {
+            GameResult::BlackWin => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::WhiteWin => "1-0",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrainingPosition {
+    pub board: Board,
+    pub score: i16,
+    pub ply: u16,
+    pub result: GameResult,
+}
+
+impl TrainingPosition {
+    pub fn pack(&self) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+        let occupied = *self.board.combined();
+        record[0..8].copy_from_slice(&occupied.0.to_le_bytes());
+
+        for (index, square) in occupied.enumerate() {
+            let piece = self
+                .board
+                .piece_on(square)
+                .map_or(0, |piece| piece.to_index());
+            let color = self
+                .board
+                .color_on(square)
+                .map_or(0, |color| color.to_index());
+            record[8 + index / 2] |= ((piece + 6 * color) as u8) << (4 * (index % 2));
+        }
+
+        record[24..26].copy_from_slice(&self.score.to_le_bytes());
+        record[26..28].copy_from_slice(&self.ply.to_le_bytes());
+        record[28] = self.result as u8;
+        record[29] = self.board.side_to_move().to_index() as u8;
+        record[30] = (self.board.castle_rights(Color::White).to_index()
+            | self.board.castle_rights(Color::Black).to_index() << 2) as u8;
+        record[31] = self
+            .board
+            .en_passant()
+            .map_or(NO_EN_PASSANT, |square| square.get_file().to_index() as u8);
+        record
+    }
+
+    // None for records that do not describe a legal position
+    pub fn unpack(record: &[u8; RECORD_SIZE]) -> Option<TrainingPosition> {
+        let mut occupancy = [0; 8];
+        occupancy.copy_from_slice(&record[0..8]);
+        let occupied = BitBoard(u64::from_le_bytes(occupancy));
+        if occupied.popcnt() > 32 {
+            return None;
+        }
+
+        let mut builder = BoardBuilder::new();
+        for (index, square) in occupied.enumerate() {
+            let code = (record[8 + index / 2] >> (4 * (index % 2))) as usize & 0xf;
+            let color = match code / 6 {
+                0 => Color::White,
+                1 => Color::Black,
+                _ => return None,
+            };
+            builder.piece(square, ALL_PIECES[code % 6], color);
+        }
+
+        let color = |byte: u8| match byte {
+            0 => Some(Color::White),
+            1 => Some(Color::Black),
+            _ => None,
+        };
+        builder
+            .side_to_move(color(record[29])?)
+            .castle_rights(
+                Color::White,
+                CastleRights::from_index(record[30] as usize & 3),
+            )
+            .castle_rights(
+                Color::Black,
+                CastleRights::from_index(record[30] as usize >> 2 & 3),
+            )
+            .en_passant(match record[31] {
+                NO_EN_PASSANT => None,
+                file if file < 8 => Some(File::from_index(file as usize)),
+                _ => return None,
+            });
+
+        if !is_convertible(&builder) {
+            return None;
+        }
+
+        let result = match record[28] {
+            0 => GameResult::BlackWin,
+            1 => GameResult::Draw,
+            2 => GameResult::WhiteWin,
+            _ => return None,
+        };
+        Some(TrainingPosition {
+            board: Board::try_from(&builder).ok()?,
+            score: i16::from_le_bytes([record[24], record[25]]),
+            ply: u16::from_le_bytes([record[26], record[27]]),
+            result,
+        })
+    }
+
+    // The clocks of the FEN are not stored, they are given from the ply
+    pub fn to_text(&self) -> String {
+        let position = Position::with_clocks(self.board, 0, self.ply as u32 / 2 + 1);
+        format!(
+            "{} | {} | {} | {}",
+            position.fen(),
+            self.score,
+            self.ply,
+            self.result.to_text()
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorSettings {
+    pub depth: SearchDepth,
+    // Stops a search early, the depth still limits it
+    pub nodes: Option<u64>,
+    pub random_plies: usize,
+    // Longer games are drawn
+    pub max_plies: usize,
+    pub skip_checks: bool,
+    // Skips positions whose best move is a capture or a promotion, their
+    // scores depend on the exchange rather than on the position
+    pub skip_captures: bool,
+    pub network: Option<Arc<Network>>,
+}
+
+impl Default for GeneratorSettings {
+    fn default() -> Self {
+        GeneratorSettings {
+            depth: 6,
+            nodes: None,
+            random_plies: 8,
+            max_plies: 400,
+            skip_checks: true,
+            skip_captures: true,
+            network: None,
+        }
+    }
+}
+
+pub struct Generator {
+    settings: GeneratorSettings,
+    context: SearchContext,
+}
+
+impl Generator {
+    pub fn new(settings: GeneratorSettings) -> Generator {
+        let (sender, _) = mpsc::channel();
+        let mut context = SearchContext::new(
+            Board::default(),
+            Arc::new(AtomicBool::new(false)),
+            sender,
+            new_hash_table(TABLE_SIZE),
+        );
+        context.evaluator.set_network(settings.network.clone());
+
+        Generator { settings, context }
+    }
+
+    // Plays one game and returns the positions that pass the filters, none if
+    // the game could not be finished
+    pub fn play_game<R: Rng>(&mut self, rng: &mut R) -> Vec<TrainingPosition> {
+        self.context.hash_table = new_hash_table(TABLE_SIZE);
+        self.context.clear_move_ordering();
+
+        let mut position = self.random_opening(rng);
+        let mut searched = vec![];
+        let result = loop {
+            if let Some(result) = game_result(&position, self.settings.max_plies) {
+                break result;
+            }
+
+            let (score, best_move) = self.search(&position, self.settings.depth);
+            // A found mate decides the game, playing it out adds nothing
+            if score.abs() >= config::MATE_THRESHOLD {
+                let side_to_move = position.board.side_to_move();
+                break match score > 0 {
+                    true => GameResult::win_for(side_to_move),
+                    false => GameResult::win_for(!side_to_move),
+                };
+            }
+
+            if !self.filtered(&position.board, best_move) {
+                searched.push((position.board, score, position.len()));
+            }
+            // The result of the game is unknown if the search returned an
+            // illegal move, so none of its positions can be labelled
+            if position.push(best_move).is_err() {
+                return vec![];
+            }
+        };
+
+        searched
+            .into_iter()
+            .map(|(board, score, ply)| TrainingPosition {
+                board,
+                score: score.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                ply: ply.min(u16::MAX as usize) as u16,
+                result,
+            })
+            .collect()
+    }
+
+    fn random_opening<R: Rng>(&mut self, rng: &mut R) -> Position {
+        loop {
+            let mut position = Position::default();
+            for _ in 0..self.settings.random_plies {
+                let Some(chess_move) = position.legal_moves().choose(rng).copied() else {
+                    break;
+                };
+                let _ = position.push(chess_move);
+            }
+
+            if game_result(&position, self.settings.max_plies).is_none() {
+                let depth = self.settings.depth.min(OPENING_CHECK_DEPTH);
+                let (score, _) = self.search(&position, depth);
+                if score.abs() <= MAX_OPENING_SCORE {
+                    return position;
+                }
+            }
+        }
+    }
+
+    fn search(&mut self, position: &Position, depth: SearchDepth) -> (PositionScore, ChessMove) {
+        self.prepare_search(position, self.settings.nodes);
+        let outcome = self.context.root_search(depth.clamp(1, config::MAX_DEPTH));
+
+        // The node limit can stop the search before the first iteration ends
+        if self.context.completed_depth == 0 {
+            self.prepare_search(position, None);
+            return self.context.root_search(1);
+        }
+        outcome
+    }
+
+    fn prepare_search(&mut self, position: &Position, node_limit: Option<u64>) {
+        self.context.reset_search(position.board);
+        // A reached node limit leaves the flag set
+        self.context.stop_flag.store(false, Ordering::Relaxed);
+        self.context.node_limit = node_limit;
+        for hash in position.repetition_hashes() {
+            self.context.set_visited(*hash);
+        }
+    }
+
+    fn filtered(&self, board: &Board, best_move: ChessMove) -> bool {
+        let in_check = *board.checkers() != EMPTY;
+        let capture = board.piece_on(best_move.get_dest()).is_some()
+            || best_move.get_promotion().is_some()
+            || (board.piece_on(best_move.get_source()) == Some(Piece::Pawn)
+                && best_move.get_source().get_file() != best_move.get_dest().get_file());
+
+        (self.settings.skip_checks && in_check) || (self.settings.skip_captures && capture)
+    }
+}
+
+// The result if the game is over by the rules or by its length
+pub fn game_result(position: &Position, max_plies: usize) -> Option<GameResult> {
+    let board = &position.board;
+    if position.legal_moves().is_empty() {
+        return Some(match *board.checkers() != EMPTY {
+            true => GameResult::win_for(!board.side_to_move()),
+            false => GameResult::Draw,
+        });
+    }
+
+    let hash = position.hash();
+    let repetitions = position
+        .repetition_hashes()
+        .iter()
+        .filter(|past| **past == hash)
+        .count();
+    let draw = position.halfmove_clock >= 100
+        || repetitions >= 3
+        || insufficient_material(board)
+        || position.len() >= max_plies;
+    draw.then_some(GameResult::Draw)
+}
+
+// Bare kings or a single minor piece against a bare king
+fn insufficient_material(board: &Board) -> bool {
+    let heavy =
+        *board.pieces(Piece::Pawn) | *board.pieces(Piece::Rook) | *board.pieces(Piece::Queen);
+    let minors = *board.pieces(Piece::Knight) | *board.pieces(Piece::Bishop);
+    heavy == EMPTY && minors.popcnt() <= 1
+}
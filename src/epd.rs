@@ -0,0 +1,14 @@
+use chess::Board;
+
+use crate::uci_command::parse_fen;
+
+// Reads the board of an EPD line, the operations after the four position
+// fields are ignored. Positions that "position fen" rejects are rejected here
+// as well, the chess crate can not handle them.
+pub fn epd_board(line: &str) -> Option<Board> {
+    let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    parse_fen(&format!("{} 0 1", fields.join(" "))).ok()
+}
@@ -1,275 +1,604 @@
+use crate::nnue::{Network, NnueEvaluator};
+use crate::params::{EvalParams, DEFAULT_PARAMS};
+use crate::pawns::{
+    attack_span, evaluate_passed_pawns, evaluate_pawns, relative_rank, PawnEntry, PawnTable,
+};
 use crate::search::PositionScore;
-use chess::Piece::{Bishop, Knight, Pawn, Queen, Rook};
-use chess::{BitBoard, Board, Color, Square, EMPTY};
+use chess::Piece;
+use chess::Piece::{Bishop, King, Knight, Pawn, Queen, Rook};
+use chess::{BitBoard, Board, ChessMove, Color, Square, ALL_PIECES, EMPTY};
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::sync::Arc;
+
+// Every term has a middlegame and an endgame value, the final score is
+// interpolated between them by the game phase
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "tune", derive(serde::Serialize, serde::Deserialize))]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Score {
+        Score { mg, eg }
+    }
+
+    // Phase runs from MAX_PHASE with all pieces on the board down to 0
+    #[inline]
+    pub fn taper(self, phase: i32) -> i32 {
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score {
+    type Output = Score;
+    #[inline]
+    fn add(self, other: Score) -> Score {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl Sub for Score {
+    type Output = Score;
+    #[inline]
+    fn sub(self, other: Score) -> Score {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+    #[inline]
+    fn neg(self) -> Score {
+        Score::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Score;
+    #[inline]
+    fn mul(self, factor: i32) -> Score {
+        Score::new(self.mg * factor, self.eg * factor)
+    }
+}
+
+impl AddAssign for Score {
+    #[inline]
+    fn add_assign(&mut self, other: Score) {
+        *self = *self + other;
+    }
+}
 
-const PAWN_VALUE: i32 = 80;
-const KNIGHT_VALUE: i32 = 300;
-const BISHOP_VALUE: i32 = 310;
-const ROOK_VALUE: i32 = 450;
-const QUEEN_VALUE: i32 = 900;
+impl SubAssign for Score {
+    #[inline]
+    fn sub_assign(&mut self, other: Score) {
+        *self = *self - other;
+    }
+}
 
-const PIN_VALUE: i32 = 5;
-const MOBILITY_VALUE: i32 = 1;
-const BISHOP_MOBILITY_VALUE: i32 = 2;
-const IN_CHECK_PENALTY: i32 = 30;
+// Phase weight of each piece type, the starting position has MAX_PHASE
+const PHASE_WEIGHTS: [i32; 6] = [0, 1, 1, 2, 4, 0];
+pub const MAX_PHASE: i32 = 24;
 
 const BLACK_HALF: BitBoard = BitBoard(0xffffffff00000000);
 const WHITE_HALF: BitBoard = BitBoard(0xffffffff);
+const LIGHT_SQUARES: BitBoard = BitBoard(0x55aa_55aa_55aa_55aa);
+const FILE_A: BitBoard = BitBoard(0x0101_0101_0101_0101);
+const FILE_H: BitBoard = BitBoard(0x8080_8080_8080_8080);
+const WHITE_THIRD_RANK: BitBoard = BitBoard(0xff_0000);
+const BLACK_THIRD_RANK: BitBoard = BitBoard(0xff00_0000_0000);
+
+// Evaluates positions with the caches of one search thread. Without the
+// "tune" feature the parameters are always the built-in ones, so the compiler
+// can fold them into the code.
+// With a network set it evaluates with NNUE instead, the search then has to
+// report its moves with push and pop.
+#[derive(Default)]
+pub struct Evaluator {
+    pawn_table: PawnTable,
+    #[cfg(feature = "tune")]
+    params: Arc<EvalParams>,
+    nnue: Option<NnueEvaluator>,
+}
 
-const CONNECTED_PAWN_VALUE: i32 = 2;
+impl Evaluator {
+    pub fn new() -> Evaluator {
+        Evaluator::default()
+    }
 
-const ATTACK_VALUE: i32 = 2;
+    // The cached pawn entries are only valid for the parameters they were
+    // computed with
+    #[cfg(feature = "tune")]
+    pub fn set_params(&mut self, params: Arc<EvalParams>) {
+        if !Arc::ptr_eq(&self.params, &params) {
+            self.params = params;
+            self.pawn_table = PawnTable::new();
+        }
+    }
 
-const INVASION_BONUS: i32 = 3;
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        match network {
+            Some(network) => {
+                let unchanged = self
+                    .nnue
+                    .as_ref()
+                    .is_some_and(|nnue| Arc::ptr_eq(nnue.network(), &network));
+                if !unchanged {
+                    self.nnue = Some(NnueEvaluator::new(network));
+                }
+            }
+            None => self.nnue = None,
+        }
+    }
 
-const SIDE_TO_MOVE_BONUS: i32 = 1;
+    #[inline]
+    pub fn push(&mut self, parent: &Board, chess_move: ChessMove, child: &Board) {
+        if let Some(nnue) = &mut self.nnue {
+            nnue.push(parent, chess_move, child);
+        }
+    }
 
-const MAX_MATERIAL: i32 = 78;
+    #[inline]
+    pub fn pop(&mut self) {
+        if let Some(nnue) = &mut self.nnue {
+            nnue.pop();
+        }
+    }
 
-macro_rules! add_value {
-    ( $table:expr, $value:expr ) => {{
-        let mut table = $table;
-        let mut i = 0;
-        while i < 64 {
-            table[i] += $value;
-            i += 1
+    #[inline]
+    pub fn evaluate(&mut self, board: &Board) -> PositionScore {
+        if let Some(nnue) = &mut self.nnue {
+            return nnue.evaluate(board);
         }
-        table
-    }};
-}
-
-#[rustfmt::skip]
-const PAWN_SHIELD_VALUE_TABLE: [i32; 64] = [
-    10, 10, 10,  0,  0,  0, 10, 10,
-     5,  5,  0,  0,  0,  0,  5,  5,
-     0,  0,  0,  0,  0,  0,  0,  0,
-     0,  0,  0,  0,  0,  0,  0,  0,
-     0,  0,  0,  0,  0,  0,  0,  0,
-     0,  0,  0,  0,  0,  0,  0,  0,
-     5,  5,  0,  0,  0,  0,  5,  5,
-    10, 10, 10,  0,  0,  0, 10, 10,
-];
-#[rustfmt::skip]
-const PAWN_TABLE_OPENING_WHITE: [i32; 64] = add_value!([
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  3,  3,  0,  0,  0, 
-    0,  0,  3, 10, 10,  3,  0,  0, 
-    0,  0,  3, 10, 10,  3,  0,  0, 
-    0,  0,  0,  3,  3,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0,
-], PAWN_VALUE);
-#[rustfmt::skip]
-const PAWN_TABLE_OPENING_BLACK: [i32; 64] = add_value!([
-     0,  0,  0,  0,  0,  0,  0,  0, 
-     0,  0,  0,  0,  0,  0,  0,  0, 
-     0,  0,  0,  3,  3,  0,  0,  0, 
-     0,  0,  3, 10, 10,  3,  0,  0, 
-     0,  0,  3, 10, 10,  3,  0,  0, 
-     0,  0,  0,  3,  3,  0,  0,  0, 
-     0,  0,  0,  0,  0,  0,  0,  0, 
-     0,  0,  0,  0,  0,  0,  0,  0,
-], PAWN_VALUE);
-#[rustfmt::skip]
-const PAWN_TABLE_ENDGAME_WHITE: [i32; 64] = add_value!([
-    10, 10, 10, 10, 10, 10, 10, 10, 
-    10, 10, 10, 10, 10, 10, 10, 10, 
-    11, 11, 11, 11, 11, 11, 11, 11, 
-    13, 13, 13, 13, 13, 13, 13, 13,  
-    16, 16, 16, 16, 16, 16, 16, 16,  
-    20, 20, 20, 20, 20, 20, 20, 20, 
-    25, 25, 25, 25, 25, 25, 25, 25, 
-    25, 25, 25, 25, 25, 25, 25, 25,
-], PAWN_VALUE);
-#[rustfmt::skip]
-const PAWN_TABLE_ENDGAME_BLACK: [i32; 64] = add_value!([
-    25, 25, 25, 25, 25, 25, 25, 25,
-    25, 25, 25, 25, 25, 25, 25, 25, 
-    20, 20, 20, 20, 20, 20, 20, 20, 
-    16, 16, 16, 16, 16, 16, 16, 16,  
-    13, 13, 13, 13, 13, 13, 13, 13,  
-    11, 11, 11, 11, 11, 11, 11, 11, 
-    10, 10, 10, 10, 10, 10, 10, 10, 
-    10, 10, 10, 10, 10, 10, 10, 10, 
-], PAWN_VALUE);
-#[rustfmt::skip]
-const KNIGHT_TABLE: [i32; 64] = add_value!([
-    2,  3,  4,  4,  4,  4,  3,  2, 
-    3,  4,  6,  6,  6,  6,  4,  3, 
-    4,  6,  8,  8,  8,  8,  6,  4, 
-    4,  6,  8,  8,  8,  8,  6,  4, 
-    4,  6,  8,  8,  8,  8,  6,  4, 
-    4,  6,  8,  8,  8,  8,  6,  4, 
-    3,  4,  6,  6,  6,  6,  4,  3,
-    2,  3,  4,  4,  4,  4,  3,  2,
-], KNIGHT_VALUE);
-#[rustfmt::skip]
-const ROOK_TABLE_WHITE: [i32; 64] = add_value!([
-    0,  0,  0,  1,  0,  1,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    2,  2,  2,  2,  2,  2,  2,  2, 
-    0,  0,  0,  0,  0,  0,  0,  0,
-], ROOK_VALUE);
-#[rustfmt::skip]
-const ROOK_TABLE_BLACK: [i32; 64] = add_value!([
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    2,  2,  2,  2,  2,  2,  2,  2, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0, 
-    0,  0,  0,  0,  0,  0,  0,  0,
-    0,  0,  0,  1,  0,  1,  0,  0, 
-], ROOK_VALUE);
-#[rustfmt::skip]
-const KING_TABLE_ENDGAME: [i32; 64] = [
-    -5, -5, -5, -5, -5, -5, -5, -5, 
-    -5,  0,  0,  0,  0,  0,  0, -5, 
-    -5,  0,  3,  3,  3,  3,  0, -5, 
-    -5,  0,  3,  4,  4,  3,  0, -5, 
-    -5,  0,  3,  4,  4,  3,  0, -5, 
-    -5,  0,  3,  3,  3,  3,  0, -5, 
-    -5,  0,  0,  0,  0,  0,  0, -5,
-    -5, -5, -5, -5, -5, -5, -5, -5,
-];
 
-#[inline]
+        #[cfg(feature = "tune")]
+        let params = &*self.params;
+        #[cfg(not(feature = "tune"))]
+        let params = &DEFAULT_PARAMS;
+
+        let pawns = self.pawn_table.probe(board, params);
+        evaluate_with_pawns(board, &pawns, params)
+    }
+}
+
+// Evaluates without any cache, the search uses an Evaluator instead
 pub fn evaluate(board: &Board) -> PositionScore {
-    // TODO: Undefended pieces, open files for rooks, past pawns , outposts
-    //       Draw by insufficient material (no pawns and total material <= bishop)
+    evaluate_with_params(board, &DEFAULT_PARAMS)
+}
 
-    let mut bitboard: BitBoard;
-    let white = board.color_combined(Color::White);
-    let black = board.color_combined(Color::Black);
-    let mut white_pieces = board.pieces(Knight) & white;
-    let mut black_pieces = board.pieces(Knight) & black;
-    let white_king_square = board.king_square(Color::White);
-    let black_king_square = board.king_square(Color::Black);
-    let mut total_material = 0;
-    let blockers = white | black;
+pub fn evaluate_with_params(board: &Board, params: &EvalParams) -> PositionScore {
+    evaluate_with_pawns(board, &evaluate_pawns(board, params), params)
+}
+
+#[inline]
+fn evaluate_with_pawns(board: &Board, pawns: &PawnEntry, params: &EvalParams) -> PositionScore {
+    // TODO: Draw by insufficient material (no pawns and total material <= bishop)
 
-    let mut score = INVASION_BONUS
-        * ((white & BLACK_HALF).popcnt() as i32 - (black & WHITE_HALF).popcnt() as i32);
+    let attacks = AttackMaps::new(board, params);
 
-    for square in white_pieces {
-        bitboard = chess::get_knight_moves(square);
-        total_material += 3;
-        score += KNIGHT_TABLE[square.to_index()];
-        score += evaluate_attack(bitboard, black_king_square);
+    // Both sides are evaluated from white's point of view
+    let mut score = evaluate_side(board, Color::White, pawns, &attacks, params)
+        - evaluate_side(board, Color::Black, pawns, &attacks, params)
+        + pawns.score;
+
+    let mut side_to_move_score = params.side_to_move_bonus;
+    if *board.checkers() != EMPTY {
+        side_to_move_score -= params.in_check_penalty;
     }
-    for square in black_pieces {
-        total_material += 3;
-        bitboard = chess::get_knight_moves(square);
-        score -= KNIGHT_TABLE[square.to_index()];
-        score -= evaluate_attack(bitboard, white_king_square);
+    match board.side_to_move() {
+        Color::White => score += side_to_move_score,
+        Color::Black => score -= side_to_move_score,
     }
 
-    white_pieces = board.pieces(Bishop) & white;
-    black_pieces = board.pieces(Bishop) & black;
+    let score = score.taper(game_phase(board));
 
-    for square in white_pieces {
-        total_material += 3;
-        bitboard = chess::get_bishop_moves(square, blockers);
-        score += BISHOP_VALUE + BISHOP_MOBILITY_VALUE * bitboard.popcnt() as i32;
-        score += evaluate_attack(bitboard, black_king_square);
+    match board.side_to_move() {
+        Color::White => score,
+        Color::Black => -score,
     }
-    for square in black_pieces {
-        total_material += 3;
-        bitboard = chess::get_bishop_moves(square, blockers);
-        score -= BISHOP_VALUE + BISHOP_MOBILITY_VALUE * bitboard.popcnt() as i32;
-        score -= evaluate_attack(bitboard, white_king_square);
+}
+
+pub fn game_phase(board: &Board) -> i32 {
+    let phase: i32 = ALL_PIECES
+        .iter()
+        .map(|piece| PHASE_WEIGHTS[piece.to_index()] * board.pieces(*piece).popcnt() as i32)
+        .sum();
+    phase.min(MAX_PHASE)
+}
+
+fn evaluate_side(
+    board: &Board,
+    color: Color,
+    pawns: &PawnEntry,
+    attacks: &AttackMaps,
+    params: &EvalParams,
+) -> Score {
+    let own = board.color_combined(color);
+    let enemy_half = match color {
+        Color::White => BLACK_HALF,
+        Color::Black => WHITE_HALF,
+    };
+
+    let mut score = params.invasion_bonus * (own & enemy_half).popcnt() as i32;
+
+    for piece in ALL_PIECES {
+        for square in board.pieces(piece) & own {
+            score += params.piece_square(piece.to_index(), relative_index(color, square));
+        }
     }
 
-    white_pieces = board.pieces(Queen) & white;
-    black_pieces = board.pieces(Queen) & black;
+    score += attacks.mobility[color.to_index()];
+    score += evaluate_piece_placement(board, color, params);
+    score += evaluate_threats(board, color, attacks, params);
+    score += evaluate_pawn_shield(
+        color,
+        board.king_square(color),
+        board.pieces(Pawn) & own,
+        params,
+    );
+    score += evaluate_king_safety(board, color, attacks, params);
+    score += evaluate_passed_pawns(board, color, pawns.passed[color.to_index()], params);
+
+    score + params.pin_value * (board.pinned() & own).popcnt() as i32
+}
+
+// Bishop pair, open files, the seventh rank, outposts, bad and trapped pieces
+pub fn evaluate_piece_placement(board: &Board, color: Color, params: &EvalParams) -> Score {
+    let own = board.color_combined(color);
+    let own_pawns = board.pieces(Pawn) & own;
+    let enemy_pawns = board.pieces(Pawn) & board.color_combined(!color);
+    let mut score = Score::new(0, 0);
+
+    let bishops = board.pieces(Bishop) & own;
+    if bishops & LIGHT_SQUARES != EMPTY && bishops & !LIGHT_SQUARES != EMPTY {
+        score += params.bishop_pair_bonus;
+    }
 
-    for square in white_pieces {
-        total_material += 9;
-        bitboard =
-            chess::get_rook_moves(square, blockers) | chess::get_bishop_moves(square, blockers);
-        score += QUEEN_VALUE + MOBILITY_VALUE * (bitboard.popcnt() as i32) / 2;
-        score += evaluate_attack(bitboard, black_king_square);
+    for square in bishops {
+        let same_color = match LIGHT_SQUARES & BitBoard::from_square(square) == EMPTY {
+            true => !LIGHT_SQUARES,
+            false => LIGHT_SQUARES,
+        };
+        score += params.bad_bishop_pawn_penalty * (own_pawns & same_color).popcnt() as i32;
+        if is_outpost(color, square, own_pawns, enemy_pawns) {
+            score += params.bishop_outpost_bonus;
+        }
+        if is_trapped_bishop(color, square, enemy_pawns) {
+            score += params.trapped_bishop_penalty;
+        }
     }
-    for square in black_pieces {
-        total_material += 9;
-        bitboard =
-            chess::get_rook_moves(square, blockers) | chess::get_bishop_moves(square, blockers);
-        score -= QUEEN_VALUE + MOBILITY_VALUE * (bitboard.popcnt() as i32) / 2;
-        score -= evaluate_attack(bitboard, white_king_square);
+
+    for square in board.pieces(Knight) & own {
+        if is_outpost(color, square, own_pawns, enemy_pawns) {
+            score += params.knight_outpost_bonus;
+        }
     }
 
-    white_pieces = board.pieces(Rook) & white;
-    black_pieces = board.pieces(Rook) & black;
+    let enemy_king_rank = relative_rank(color, board.king_square(!color));
+    let seventh_rank = match color {
+        Color::White => chess::get_rank(chess::Rank::Seventh),
+        Color::Black => chess::get_rank(chess::Rank::Second),
+    };
+    let king_square = board.king_square(color);
+
+    for square in board.pieces(Rook) & own {
+        score += match file_type(square, own_pawns, enemy_pawns) {
+            FileType::Open => params.rook_open_file_bonus,
+            FileType::SemiOpen => params.rook_semi_open_file_bonus,
+            FileType::Closed => Score::new(0, 0),
+        };
+        if relative_rank(color, square) == 6
+            && (enemy_king_rank == 7 || enemy_pawns & seventh_rank != EMPTY)
+        {
+            score += params.rook_on_seventh_bonus;
+        }
+        if is_trapped_rook(color, square, king_square) {
+            score += params.trapped_rook_penalty;
+        }
+    }
 
-    for square in white_pieces {
-        total_material += 5;
-        bitboard = chess::get_rook_moves(square, blockers);
-        score += ROOK_TABLE_WHITE[square.to_index()] + MOBILITY_VALUE * bitboard.popcnt() as i32;
-        score += evaluate_attack(bitboard, black_king_square);
+    for square in board.pieces(Queen) & own {
+        score += match file_type(square, own_pawns, enemy_pawns) {
+            FileType::Open => params.queen_open_file_bonus,
+            FileType::SemiOpen => params.queen_semi_open_file_bonus,
+            FileType::Closed => Score::new(0, 0),
+        };
     }
-    for square in black_pieces {
-        total_material += 5;
-        bitboard = chess::get_rook_moves(square, blockers);
-        score -= ROOK_TABLE_BLACK[square.to_index()] + MOBILITY_VALUE * bitboard.popcnt() as i32;
-        score -= evaluate_attack(bitboard, white_king_square);
+
+    score
+}
+
+enum FileType {
+    Open,
+    SemiOpen,
+    Closed,
+}
+
+#[inline]
+fn file_type(square: Square, own_pawns: BitBoard, enemy_pawns: BitBoard) -> FileType {
+    let file = chess::get_file(square.get_file());
+    match (file & own_pawns == EMPTY, file & enemy_pawns == EMPTY) {
+        (true, true) => FileType::Open,
+        (true, false) => FileType::SemiOpen,
+        _ => FileType::Closed,
     }
+}
 
-    white_pieces = board.pieces(Pawn) & white;
-    black_pieces = board.pieces(Pawn) & black;
+#[inline]
+fn is_outpost(color: Color, square: Square, own_pawns: BitBoard, enemy_pawns: BitBoard) -> bool {
+    (3..=5).contains(&relative_rank(color, square))
+        && chess::get_pawn_attacks(square, !color, own_pawns) != EMPTY
+        && attack_span(color, square) & enemy_pawns == EMPTY
+}
 
-    total_material += white_pieces.popcnt() as i32 + black_pieces.popcnt() as i32;
+#[inline]
+fn is_trapped_bishop(color: Color, square: Square, enemy_pawns: BitBoard) -> bool {
+    if relative_rank(color, square) != 6 {
+        return false;
+    }
+    // The pawn that blocks the way back, one rank behind on the inner file
+    let blocker = match square.get_file().to_index() {
+        0 => square.right(),
+        7 => square.left(),
+        _ => None,
+    }
+    .and_then(|square| match color {
+        Color::White => square.down(),
+        Color::Black => square.up(),
+    });
+    blocker.is_some_and(|blocker| enemy_pawns & BitBoard::from_square(blocker) != EMPTY)
+}
 
-    let game_phase = (100 * total_material) / MAX_MATERIAL;
+#[inline]
+fn is_trapped_rook(color: Color, square: Square, king_square: Square) -> bool {
+    if relative_rank(color, square) != 0 || relative_rank(color, king_square) != 0 {
+        return false;
+    }
+    let rook_file = square.get_file().to_index();
+    match king_square.get_file().to_index() {
+        1 | 2 => rook_file < king_square.get_file().to_index(),
+        5 | 6 => rook_file > king_square.get_file().to_index(),
+        _ => false,
+    }
+}
 
-    score += game_phase * evaluate_pawn_shield(white_king_square, white_pieces) / 100;
-    score -= game_phase * evaluate_pawn_shield(black_king_square, black_pieces) / 100;
+#[inline]
+fn evaluate_pawn_shield(
+    color: Color,
+    king_square: Square,
+    pawns: BitBoard,
+    params: &EvalParams,
+) -> Score {
+    let index = relative_index(color, king_square);
+    let shield = params.pawn_shield[index / 8][index % 8]
+        * (chess::get_king_moves(king_square) & pawns).popcnt() as i32;
+    Score::new(shield, 0)
+}
 
-    for square in white_pieces {
-        score += evaluate_connected_pawns(square, Color::Black, &white_pieces);
-        score += (PAWN_TABLE_OPENING_WHITE[square.to_index()] * game_phase
-            + PAWN_TABLE_ENDGAME_WHITE[square.to_index()] * (100 - game_phase))
-            / 100;
+pub fn king_safety(board: &Board, color: Color, params: &EvalParams) -> Score {
+    evaluate_king_safety(board, color, &AttackMaps::new(board, params), params)
+}
+
+// The penalty for the dangers to the king of the given color
+fn evaluate_king_safety(
+    board: &Board,
+    color: Color,
+    attacks: &AttackMaps,
+    params: &EvalParams,
+) -> Score {
+    let king_square = board.king_square(color);
+    let enemy = board.color_combined(!color);
+    let enemy_attacks = &attacks.by_piece[(!color).to_index()];
+    let blockers = *board.combined();
+
+    let bishop_checks = chess::get_bishop_moves(king_square, blockers);
+    let rook_checks = chess::get_rook_moves(king_square, blockers);
+    let check_squares = [
+        EMPTY,
+        chess::get_knight_moves(king_square),
+        bishop_checks,
+        rook_checks,
+        bishop_checks | rook_checks,
+    ];
+    let safe = !(*enemy | attacks.all[color.to_index()]);
+
+    let attackers = attacks.king_attackers[(!color).to_index()];
+    let mut units = attacks.king_attack_units[(!color).to_index()];
+    for piece in [Knight, Bishop, Rook, Queen] {
+        if enemy_attacks[piece.to_index()] & check_squares[piece.to_index()] & safe != EMPTY {
+            units += params.safe_check_weights[piece.to_index()];
+        }
     }
-    for square in black_pieces {
-        score -= evaluate_connected_pawns(square, Color::Black, &black_pieces);
-        score -= (PAWN_TABLE_OPENING_BLACK[square.to_index()] * game_phase
-            + PAWN_TABLE_ENDGAME_BLACK[square.to_index()] * (100 - game_phase))
-            / 100;
+
+    let mut score = Score::new(0, 0);
+    // A lone attacker is no real threat
+    if attackers >= 2 {
+        let danger = params.king_danger(units);
+        score -= Score::new(danger, danger / params.king_danger_endgame_divisor.max(1));
     }
 
-    score += KING_TABLE_ENDGAME[white_king_square.to_index()] * (100 - game_phase) / 100;
+    let own_pawns = board.pieces(Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Pawn) & enemy;
+    let king_file = king_square.get_file().to_index();
+    let king_rank = king_square.get_rank().to_index() as i32;
+
+    for file in king_file.saturating_sub(1)..=(king_file + 1).min(7) {
+        let file_mask = chess::get_file(chess::File::from_index(file));
+        if file_mask & own_pawns == EMPTY {
+            score += match file_mask & enemy_pawns == EMPTY {
+                true => params.king_open_file_penalty,
+                false => params.king_half_open_file_penalty,
+            };
+        }
+
+        // Enemy pawns advancing towards the king
+        for square in file_mask & enemy_pawns {
+            let distance = square.get_rank().to_index() as i32 - king_rank;
+            let distance = match color {
+                Color::White => distance,
+                Color::Black => -distance,
+            };
+            if distance > 0 {
+                score += params.pawn_storm_penalties[distance as usize];
+            }
+        }
+    }
 
-    score += PIN_VALUE
-        * ((board.pinned() & white).popcnt() as i32 - (board.pinned() & black).popcnt() as i32);
+    score
+}
 
-    if board.side_to_move() == Color::Black {
-        score = -score
+// Pieces attacked by cheaper pieces, hanging pieces and pawn pushes that
+// would attack a piece, for the threats made by the given color
+pub fn evaluate_threats(
+    board: &Board,
+    color: Color,
+    attacks: &AttackMaps,
+    params: &EvalParams,
+) -> Score {
+    let own_attacks = &attacks.by_piece[color.to_index()];
+    let enemy_attacks = &attacks.by_piece[(!color).to_index()];
+    let enemy = board.color_combined(!color);
+    let enemy_pieces = enemy & !(board.pieces(Pawn) | board.pieces(King));
+    let heavy_pieces = enemy & (board.pieces(Rook) | board.pieces(Queen));
+    let minor_attacks = own_attacks[Knight.to_index()] | own_attacks[Bishop.to_index()];
+
+    let mut score = params.threat_by_pawn
+        * (own_attacks[Pawn.to_index()] & enemy_pieces).popcnt() as i32
+        + params.threat_by_minor * (minor_attacks & heavy_pieces).popcnt() as i32
+        + params.threat_by_rook
+            * (own_attacks[Rook.to_index()] & enemy & board.pieces(Queen)).popcnt() as i32;
+
+    let hanging = enemy_pieces & attacks.all[color.to_index()] & !attacks.all[(!color).to_index()];
+    score += params.hanging_piece * hanging.popcnt() as i32;
+
+    // A push is safe when no enemy pawn attacks the square and it is defended
+    // or not attacked at all
+    let empty = !*board.combined();
+    let third_rank = match color {
+        Color::White => WHITE_THIRD_RANK,
+        Color::Black => BLACK_THIRD_RANK,
     };
+    let own_pawns = board.pieces(Pawn) & board.color_combined(color);
+    let mut pushes = pawn_pushes(color, own_pawns) & empty;
+    pushes |= pawn_pushes(color, pushes & third_rank) & empty;
+    let safe_pushes = pushes
+        & !enemy_attacks[Pawn.to_index()]
+        & (attacks.all[color.to_index()] | !attacks.all[(!color).to_index()]);
+    let push_threats =
+        pawn_attacks(color, safe_pushes) & enemy_pieces & !own_attacks[Pawn.to_index()];
+    score += params.pawn_push_threat * push_threats.popcnt() as i32;
+
+    score
+}
 
-    if *board.checkers() != EMPTY {
-        score -= IN_CHECK_PENALTY;
+// The attacks of both colors by piece type, computed once per evaluation.
+// The mobility and the attacks on the enemy king zone are counted on the way
+// as they need the attacks of every single piece.
+pub struct AttackMaps {
+    pub by_piece: [[BitBoard; 6]; 2],
+    pub all: [BitBoard; 2],
+    mobility: [Score; 2],
+    king_attackers: [i32; 2],
+    king_attack_units: [i32; 2],
+}
+
+impl AttackMaps {
+    pub fn new(board: &Board, params: &EvalParams) -> AttackMaps {
+        let mut maps = AttackMaps {
+            by_piece: [[EMPTY; 6]; 2],
+            all: [EMPTY; 2],
+            mobility: [Score::new(0, 0); 2],
+            king_attackers: [0; 2],
+            king_attack_units: [0; 2],
+        };
+        let blockers = *board.combined();
+
+        // The pawn attacks of both sides are needed for the mobility
+        for color in [Color::White, Color::Black] {
+            let own = board.color_combined(color);
+            let attacks = &mut maps.by_piece[color.to_index()];
+            attacks[Pawn.to_index()] = pawn_attacks(color, board.pieces(Pawn) & own);
+            attacks[King.to_index()] = chess::get_king_moves(board.king_square(color));
+        }
+
+        for color in [Color::White, Color::Black] {
+            let index = color.to_index();
+            let own = board.color_combined(color);
+            let mobility_area = !(maps.by_piece[(!color).to_index()][Pawn.to_index()]
+                | own & (board.pieces(Pawn) | board.pieces(King)));
+            let enemy_king = board.king_square(!color);
+            let king_zone = chess::get_king_moves(enemy_king) | BitBoard::from_square(enemy_king);
+
+            for piece in [Knight, Bishop, Rook, Queen] {
+                for square in board.pieces(piece) & own {
+                    let attacks = piece_attacks_from(piece, square, blockers);
+                    maps.by_piece[index][piece.to_index()] |= attacks;
+                    maps.mobility[index] +=
+                        mobility_value(piece, (attacks & mobility_area).popcnt() as i32, params);
+
+                    let zone_attacks = (attacks & king_zone).popcnt() as i32;
+                    if zone_attacks > 0 {
+                        maps.king_attackers[index] += 1;
+                        maps.king_attack_units[index] +=
+                            params.king_attack_weights[piece.to_index()] * zone_attacks;
+                    }
+                }
+            }
+            maps.all[index] = maps.by_piece[index]
+                .iter()
+                .fold(EMPTY, |all, attacks| all | *attacks);
+        }
+        maps
     }
+}
 
-    score + SIDE_TO_MOVE_BONUS
+#[inline]
+fn mobility_value(piece: Piece, squares: i32, params: &EvalParams) -> Score {
+    match piece {
+        Bishop => params.bishop_mobility * squares,
+        Rook => params.rook_mobility * squares,
+        Queen => params.queen_mobility * (squares / 2),
+        _ => Score::new(0, 0),
+    }
 }
 
 #[inline]
-fn evaluate_connected_pawns(square: Square, color: Color, pawns: &BitBoard) -> i32 {
-    CONNECTED_PAWN_VALUE * chess::get_pawn_attacks(square, color, *pawns).popcnt() as i32
+fn pawn_attacks(color: Color, pawns: BitBoard) -> BitBoard {
+    let west = pawns & !FILE_A;
+    let east = pawns & !FILE_H;
+    match color {
+        Color::White => BitBoard((west.0 << 7) | (east.0 << 9)),
+        Color::Black => BitBoard((west.0 >> 9) | (east.0 >> 7)),
+    }
 }
 
 #[inline]
-fn evaluate_pawn_shield(king_square: Square, pawns: BitBoard) -> i32 {
-    PAWN_SHIELD_VALUE_TABLE[king_square.to_index()]
-        * (chess::get_king_moves(king_square) & pawns).popcnt() as i32
+fn pawn_pushes(color: Color, pawns: BitBoard) -> BitBoard {
+    match color {
+        Color::White => BitBoard(pawns.0 << 8),
+        Color::Black => BitBoard(pawns.0 >> 8),
+    }
 }
 
 #[inline]
-fn evaluate_attack(attack_bitboard: BitBoard, king_square: Square) -> i32 {
-    ATTACK_VALUE * (attack_bitboard & chess::get_king_moves(king_square)).popcnt() as i32
+fn piece_attacks_from(piece: Piece, square: Square, blockers: BitBoard) -> BitBoard {
+    match piece {
+        Knight => chess::get_knight_moves(square),
+        Bishop => chess::get_bishop_moves(square, blockers),
+        Rook => chess::get_rook_moves(square, blockers),
+        Queen => {
+            chess::get_rook_moves(square, blockers) | chess::get_bishop_moves(square, blockers)
+        }
+        _ => EMPTY,
+    }
+}
+
+// The index of the square mirrored to white's side for black
+#[inline]
+fn relative_index(color: Color, square: Square) -> usize {
+    match color {
+        Color::White => square.to_index(),
+        Color::Black => square.to_index() ^ 56,
+    }
 }
@@ -1,8 +1,27 @@
+pub mod chess960;
 pub mod config;
+pub mod datagen;
+pub mod epd;
 pub mod eval;
 pub mod movelist;
+pub mod nnue;
+pub mod options;
+pub mod params;
+pub mod pawns;
+pub mod position;
+pub mod printer;
+pub mod protocol;
 pub mod search;
+#[cfg(feature = "stats")]
+pub mod stats;
 pub mod table;
 pub mod test_utils;
 pub mod threading;
+pub mod time_control;
+#[cfg(feature = "tree")]
+pub mod tree;
+#[cfg(feature = "tune")]
+pub mod tuner;
 pub mod uci;
+pub mod uci_command;
+pub mod xboard;
@@ -1,6 +1,5 @@
-
-use rust_chess::uci;
+use rust_chess::protocol;
 
 fn main() {
-    uci::uci_mode();
+    protocol::engine_mode();
 }
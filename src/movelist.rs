@@ -1,4 +1,3 @@
-use std::rc::Rc;
 use std::vec;
 
 use chess::{BitBoard, Board, ChessMove, MoveGen, Piece, EMPTY};
@@ -6,18 +5,33 @@ use chess::{BitBoard, Board, ChessMove, MoveGen, Piece, EMPTY};
 use crate::config::MVV_ORDERING;
 use crate::search::{opponent_pieces_of_type, KillerMoves, Table64by64};
 
-enum MoveOrderingStage {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MoveOrderingStage {
     Hash,
     Captures,
     KillerOne,
     KillerTwo,
+    Countermove,
     Quiet,
 }
 
+impl MoveOrderingStage {
+    pub const COUNT: usize = 6;
+    pub const ALL: [MoveOrderingStage; MoveOrderingStage::COUNT] = [
+        MoveOrderingStage::Hash,
+        MoveOrderingStage::Captures,
+        MoveOrderingStage::KillerOne,
+        MoveOrderingStage::KillerTwo,
+        MoveOrderingStage::Countermove,
+        MoveOrderingStage::Quiet,
+    ];
+}
+
 pub struct MoveList<'a> {
     board: &'a Board,
     hash_move: Option<ChessMove>,
     killers: KillerMoves,
+    countermove: Option<ChessMove>,
     history_table: Table64by64,
     quiets: Option<Vec<ChessMove>>,
     stage: MoveOrderingStage,
@@ -30,12 +44,20 @@ impl<'a> MoveList<'a> {
         board: &'a Board,
         hash_move: Option<ChessMove>,
         killers: KillerMoves,
+        countermove: Option<ChessMove>,
         history_table: Table64by64,
     ) -> MoveList<'a> {
+        // The countermove is only tried if it is a quiet move that is not
+        // already tried as hash move or killer
+        let countermove = countermove.filter(|chess_move| {
+            !is_killer_or_hash(*chess_move, killers, hash_move) && is_quiet(board, *chess_move)
+        });
+
         MoveList {
             board,
             hash_move,
             killers,
+            countermove,
             history_table,
             quiets: None,
             stage: MoveOrderingStage::Hash,
@@ -87,18 +109,28 @@ impl<'a> Iterator for MoveList<'a> {
                 }
             }
             MoveOrderingStage::KillerTwo => {
-                self.stage = MoveOrderingStage::Quiet;
-                self.movegen.set_iterator_mask(!EMPTY);
+                self.stage = MoveOrderingStage::Countermove;
 
                 match self.board.legal(self.killers.two) {
                     true => Some(self.killers.two),
                     false => self.next(),
                 }
             }
+            MoveOrderingStage::Countermove => {
+                self.stage = MoveOrderingStage::Quiet;
+                self.movegen.set_iterator_mask(!EMPTY);
+
+                match self.countermove {
+                    Some(countermove) if self.board.legal(countermove) => Some(countermove),
+                    _ => self.next(),
+                }
+            }
             MoveOrderingStage::Quiet => match self.quiets.as_mut() {
                 Some(quiet_moves) => match quiet_moves.pop() {
                     Some(chess_move) => {
-                        match is_killer_or_hash(chess_move, self.killers, self.hash_move) {
+                        match is_killer_or_hash(chess_move, self.killers, self.hash_move)
+                            || self.countermove == Some(chess_move)
+                        {
                             true => self.next(),
                             false => Some(chess_move),
                         }
@@ -108,11 +140,8 @@ impl<'a> Iterator for MoveList<'a> {
                 },
                 None => {
                     let mut move_vector = vec![];
-                    loop {
-                        match self.movegen.next() {
-                            Some(chess_move) => move_vector.push(chess_move), //self.quiets.as_mut()?.push(chess_move),
-                            None => break,
-                        }
+                    for chess_move in &mut self.movegen {
+                        move_vector.push(chess_move);
                     }
                     move_vector.sort_unstable_by_key(|m| {
                         // TODO: sort by history heuristic
@@ -134,6 +163,31 @@ impl<'a> Iterator for MoveList<'a> {
     type Item = ChessMove;
 }
 
+// The stage in which a move list with the given ordering hints yields the
+// move, used for statistics only
+#[cfg(feature = "stats")]
+pub fn move_stage(
+    board: &Board,
+    chess_move: ChessMove,
+    hash_move: Option<ChessMove>,
+    killers: KillerMoves,
+    countermove: Option<ChessMove>,
+) -> MoveOrderingStage {
+    if hash_move == Some(chess_move) {
+        MoveOrderingStage::Hash
+    } else if !is_quiet(board, chess_move) {
+        MoveOrderingStage::Captures
+    } else if chess_move == killers.one {
+        MoveOrderingStage::KillerOne
+    } else if chess_move == killers.two {
+        MoveOrderingStage::KillerTwo
+    } else if countermove == Some(chess_move) {
+        MoveOrderingStage::Countermove
+    } else {
+        MoveOrderingStage::Quiet
+    }
+}
+
 #[inline]
 fn is_killer_or_hash(
     chess_move: ChessMove,
@@ -143,6 +197,14 @@ fn is_killer_or_hash(
     chess_move == killers.one || chess_move == killers.two || hash_move == Some(chess_move)
 }
 
+#[inline]
+fn is_quiet(board: &Board, chess_move: ChessMove) -> bool {
+    let is_pawn = board.piece_on(chess_move.get_source()) == Some(Piece::Pawn);
+    let changes_file = chess_move.get_source().get_file() != chess_move.get_dest().get_file();
+
+    board.piece_on(chess_move.get_dest()).is_none() && !(is_pawn && changes_file)
+}
+
 #[inline]
 fn get_targets(board: &Board, piece_type: Piece) -> BitBoard {
     match piece_type {
@@ -0,0 +1,477 @@
+use chess::{Board, ChessMove, Color, Piece, Square};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::search::PositionScore;
+
+// Efficiently updatable neural network evaluation. The first layer has one
+// input for every (king square, piece, square) combination of the HalfKP
+// feature set, seen from both sides. As a move only changes a few of them,
+// its output, the accumulator, is updated incrementally along the search.
+//
+// Network file format, all numbers little endian:
+//
+//   magic           4 bytes           "RCNN"
+//   version         u32               1
+//   feature set     u32               0 = HalfKP
+//   l1              u32               accumulator size, a multiple of 16
+//   l2              u32               hidden layer size, a multiple of 32
+//   output scale    i32               the output divided by it is in centipawns
+//   feature biases  i16 × l1
+//   feature weights i16 × 40960 × l1  one row of l1 per feature
+//   hidden biases   i32 × l2
+//   hidden weights  i8 × l2 × 2·l1    one row per neuron, the half of the
+//                                     side to move first
+//   output bias     i32
+//   output weights  i8 × l2
+//
+// The HalfKP feature of a piece other than a king from one side's view is
+//   king square × 640 + (piece type × 2 + 1 if it is an enemy piece) × 64 + square
+// with the squares flipped vertically for black, so both sides share weights.
+//
+// Inference: both accumulators are clipped to 0..=127, the hidden sums are
+// shifted right by HIDDEN_SHIFT and clipped the same way, the output sum is
+// divided by the output scale.
+
+const MAGIC: &[u8; 4] = b"RCNN";
+const VERSION: u32 = 1;
+const HALF_KP: u32 = 0;
+pub const FEATURES: usize = 64 * 640;
+const HIDDEN_SHIFT: i32 = 6;
+const CLIP: i16 = 127;
+// Network scores stay clear of the mate scores
+const MAX_SCORE: PositionScore = 20000;
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(std::io::Error),
+    Format(String),
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Io(error) => write!(f, "{error}"),
+            NetworkError::Format(reason) => write!(f, "invalid network: {reason}"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Network {
+    l1: usize,
+    l2: usize,
+    output_scale: i32,
+    feature_biases: Vec<i16>,
+    feature_weights: Vec<i16>,
+    hidden_biases: Vec<i32>,
+    hidden_weights: Vec<i8>,
+    output_bias: i32,
+    output_weights: Vec<i8>,
+    avx2: bool,
+}
+
+// The weights are far too many to print
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Network(HalfKP {}x2-{}-1)", self.l1, self.l2)
+    }
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network, NetworkError> {
+        Network::from_bytes(&std::fs::read(path).map_err(NetworkError::Io)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, NetworkError> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        if reader.take(4)? != MAGIC {
+            return Err(NetworkError::Format("wrong magic".to_string()));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(NetworkError::Format(format!("version {version}")));
+        }
+        let feature_set = reader.u32()?;
+        if feature_set != HALF_KP {
+            return Err(NetworkError::Format(format!("feature set {feature_set}")));
+        }
+        let l1 = reader.u32()? as usize;
+        let l2 = reader.u32()? as usize;
+        if l1 == 0 || !l1.is_multiple_of(16) || l2 == 0 || !l2.is_multiple_of(32) {
+            return Err(NetworkError::Format(format!("layer sizes {l1} and {l2}")));
+        }
+        let output_scale = reader.i32()?;
+        if output_scale <= 0 {
+            return Err(NetworkError::Format(format!("output scale {output_scale}")));
+        }
+
+        let network = Network {
+            l1,
+            l2,
+            output_scale,
+            feature_biases: reader.i16s(l1)?,
+            feature_weights: reader.i16s(FEATURES * l1)?,
+            hidden_biases: reader.i32s(l2)?,
+            hidden_weights: reader.i8s(l2 * 2 * l1)?,
+            output_bias: reader.i32()?,
+            output_weights: reader.i8s(l2)?,
+            avx2: avx2_available(),
+        };
+        match reader.position == bytes.len() {
+            true => Ok(network),
+            false => Err(NetworkError::Format("trailing bytes".to_string())),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for value in [VERSION, HALF_KP, self.l1 as u32, self.l2 as u32] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(self.output_scale.to_le_bytes());
+        bytes.extend(self.feature_biases.iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend(self.feature_weights.iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend(self.hidden_biases.iter().flat_map(|v| v.to_le_bytes()));
+        bytes.extend(self.hidden_weights.iter().map(|v| *v as u8));
+        bytes.extend(self.output_bias.to_le_bytes());
+        bytes.extend(self.output_weights.iter().map(|v| *v as u8));
+        bytes
+    }
+
+    // SIMD is only used when the CPU supports it, disabling it is for tests
+    pub fn set_simd(&mut self, enabled: bool) {
+        self.avx2 = enabled && avx2_available();
+    }
+
+    #[inline]
+    fn row(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.l1..(feature + 1) * self.l1]
+    }
+
+    fn refresh(&self, accumulator: &mut [i16], board: &Board, perspective: Color) {
+        accumulator.copy_from_slice(&self.feature_biases);
+        let king = board.king_square(perspective);
+        for color in [Color::White, Color::Black] {
+            for piece in [
+                Piece::Pawn,
+                Piece::Knight,
+                Piece::Bishop,
+                Piece::Rook,
+                Piece::Queen,
+            ] {
+                for square in board.pieces(piece) & board.color_combined(color) {
+                    let feature = feature(perspective, king, piece, color, square);
+                    self.add_row(accumulator, feature);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn add_row(&self, accumulator: &mut [i16], feature: usize) {
+        let row = self.row(feature);
+        #[cfg(target_arch = "x86_64")]
+        if self.avx2 {
+            // Safety: AVX2 was detected and the length is a multiple of 16
+            unsafe { avx2::add_row(accumulator, row) };
+            return;
+        }
+        for (value, weight) in accumulator.iter_mut().zip(row) {
+            *value = value.wrapping_add(*weight);
+        }
+    }
+
+    #[inline]
+    fn sub_row(&self, accumulator: &mut [i16], feature: usize) {
+        let row = self.row(feature);
+        #[cfg(target_arch = "x86_64")]
+        if self.avx2 {
+            // Safety: AVX2 was detected and the length is a multiple of 16
+            unsafe { avx2::sub_row(accumulator, row) };
+            return;
+        }
+        for (value, weight) in accumulator.iter_mut().zip(row) {
+            *value = value.wrapping_sub(*weight);
+        }
+    }
+
+    #[inline]
+    fn dot(&self, input: &[u8], weights: &[i8]) -> i32 {
+        #[cfg(target_arch = "x86_64")]
+        if self.avx2 {
+            // Safety: AVX2 was detected and the length is a multiple of 32
+            return unsafe { avx2::dot(input, weights) };
+        }
+        input
+            .iter()
+            .zip(weights)
+            .map(|(input, weight)| *input as i32 * *weight as i32)
+            .sum()
+    }
+
+    // The score for the side to move, the buffers have the sizes 2·l1 and l2
+    fn forward(
+        &self,
+        accumulator: &Accumulator,
+        side_to_move: Color,
+        input: &mut [u8],
+        hidden: &mut [u8],
+    ) -> PositionScore {
+        let (own, other) = input.split_at_mut(self.l1);
+        for (values, half) in [
+            (&accumulator.values[side_to_move.to_index()], own),
+            (&accumulator.values[(!side_to_move).to_index()], other),
+        ] {
+            for (input, value) in half.iter_mut().zip(values) {
+                *input = (*value).clamp(0, CLIP) as u8;
+            }
+        }
+
+        let row_size = 2 * self.l1;
+        for (neuron, output) in hidden.iter_mut().enumerate() {
+            let weights = &self.hidden_weights[neuron * row_size..(neuron + 1) * row_size];
+            let sum = self.hidden_biases[neuron] + self.dot(input, weights);
+            *output = (sum >> HIDDEN_SHIFT).clamp(0, CLIP as i32) as u8;
+        }
+
+        let output = self.output_bias + self.dot(hidden, &self.output_weights);
+        (output / self.output_scale).clamp(-MAX_SCORE, MAX_SCORE)
+    }
+}
+
+#[inline]
+fn feature(perspective: Color, king: Square, piece: Piece, color: Color, square: Square) -> usize {
+    let orient = |square: Square| match perspective {
+        Color::White => square.to_index(),
+        Color::Black => square.to_index() ^ 56,
+    };
+    let piece_index = piece.to_index() * 2 + (color != perspective) as usize;
+    orient(king) * 640 + piece_index * 64 + orient(square)
+}
+
+fn avx2_available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    return is_x86_feature_detected!("avx2");
+    #[cfg(not(target_arch = "x86_64"))]
+    return false;
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], NetworkError> {
+        let end = self.position + count;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or_else(|| NetworkError::Format("file too short".to_string()))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, NetworkError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, NetworkError> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn i32s(&mut self, count: usize) -> Result<Vec<i32>, NetworkError> {
+        (0..count).map(|_| self.i32()).collect()
+    }
+
+    fn i16s(&mut self, count: usize) -> Result<Vec<i16>, NetworkError> {
+        let bytes = self.take(2 * count)?;
+        Ok(bytes
+            .chunks_exact(2)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect())
+    }
+
+    fn i8s(&mut self, count: usize) -> Result<Vec<i8>, NetworkError> {
+        Ok(self.take(count)?.iter().map(|byte| *byte as i8).collect())
+    }
+}
+
+#[derive(Clone)]
+struct Accumulator {
+    values: [Vec<i16>; 2],
+    // The position the values belong to, None before the first use
+    hash: Option<u64>,
+}
+
+// The accumulators of the positions on the current search path. The search
+// pushes a move before it descends and pops it when it returns. Every
+// accumulator remembers the hash of its position, so a position reached in any
+// other way is simply computed from scratch.
+pub struct NnueEvaluator {
+    network: Arc<Network>,
+    stack: Vec<Accumulator>,
+    ply: usize,
+    input: Vec<u8>,
+    hidden: Vec<u8>,
+}
+
+impl NnueEvaluator {
+    pub fn new(network: Arc<Network>) -> NnueEvaluator {
+        let accumulator = Accumulator {
+            values: [vec![0; network.l1], vec![0; network.l1]],
+            hash: None,
+        };
+        NnueEvaluator {
+            stack: vec![accumulator],
+            ply: 0,
+            input: vec![0; 2 * network.l1],
+            hidden: vec![0; network.l2],
+            network,
+        }
+    }
+
+    pub fn network(&self) -> &Arc<Network> {
+        &self.network
+    }
+
+    pub fn evaluate(&mut self, board: &Board) -> PositionScore {
+        self.ensure_current(board);
+        self.network.forward(
+            &self.stack[self.ply],
+            board.side_to_move(),
+            &mut self.input,
+            &mut self.hidden,
+        )
+    }
+
+    pub fn push(&mut self, parent: &Board, chess_move: ChessMove, child: &Board) {
+        self.ensure_current(parent);
+        if self.ply + 1 == self.stack.len() {
+            self.stack.push(self.stack[self.ply].clone());
+        }
+        let (path, rest) = self.stack.split_at_mut(self.ply + 1);
+        let (current, next) = (&path[self.ply], &mut rest[0]);
+        self.ply += 1;
+        next.hash = Some(child.get_hash());
+
+        let source = chess_move.get_source();
+        let destination = chess_move.get_dest();
+        let Some(piece) = parent.piece_on(source) else {
+            return self.refresh(child);
+        };
+        // The king square is part of every feature of its side, so a king move
+        // and castling start over
+        if piece == Piece::King {
+            return self.refresh(child);
+        }
+
+        let mover = parent.side_to_move();
+        // The moved piece and at most one captured piece leave their squares
+        let mut removed = [(piece, mover, source); 2];
+        let mut removed_count = 1;
+        let added = (
+            chess_move.get_promotion().unwrap_or(piece),
+            mover,
+            destination,
+        );
+        if let Some(captured) = parent.piece_on(destination) {
+            removed[1] = (captured, !mover, destination);
+            removed_count = 2;
+        } else if piece == Piece::Pawn && source.get_file() != destination.get_file() {
+            // En passant
+            let square = Square::make_square(source.get_rank(), destination.get_file());
+            removed[1] = (Piece::Pawn, !mover, square);
+            removed_count = 2;
+        }
+
+        for perspective in [Color::White, Color::Black] {
+            let king = child.king_square(perspective);
+            let values = &mut next.values[perspective.to_index()];
+            values.copy_from_slice(&current.values[perspective.to_index()]);
+            for (piece, color, square) in &removed[..removed_count] {
+                let feature = feature(perspective, king, *piece, *color, *square);
+                self.network.sub_row(values, feature);
+            }
+            let (piece, color, square) = added;
+            self.network
+                .add_row(values, feature(perspective, king, piece, color, square));
+        }
+    }
+
+    pub fn pop(&mut self) {
+        self.ply = self.ply.saturating_sub(1);
+    }
+
+    #[inline]
+    fn ensure_current(&mut self, board: &Board) {
+        if self.stack[self.ply].hash != Some(board.get_hash()) {
+            self.refresh(board);
+        }
+    }
+
+    fn refresh(&mut self, board: &Board) {
+        let accumulator = &mut self.stack[self.ply];
+        for perspective in [Color::White, Color::Black] {
+            self.network.refresh(
+                &mut accumulator.values[perspective.to_index()],
+                board,
+                perspective,
+            );
+        }
+        accumulator.hash = Some(board.get_hash());
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    // The callers make sure that the CPU supports AVX2 and that the lengths
+    // are multiples of 16 values for the rows and 32 for the dot product
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn add_row(accumulator: &mut [i16], row: &[i16]) {
+        for (values, weights) in accumulator.chunks_exact_mut(16).zip(row.chunks_exact(16)) {
+            let sum = _mm256_add_epi16(
+                _mm256_loadu_si256(values.as_ptr() as *const __m256i),
+                _mm256_loadu_si256(weights.as_ptr() as *const __m256i),
+            );
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, sum);
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn sub_row(accumulator: &mut [i16], row: &[i16]) {
+        for (values, weights) in accumulator.chunks_exact_mut(16).zip(row.chunks_exact(16)) {
+            let difference = _mm256_sub_epi16(
+                _mm256_loadu_si256(values.as_ptr() as *const __m256i),
+                _mm256_loadu_si256(weights.as_ptr() as *const __m256i),
+            );
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, difference);
+        }
+    }
+
+    // The inputs are at most 127, so the pairwise i16 sums cannot saturate
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn dot(input: &[u8], weights: &[i8]) -> i32 {
+        let ones = _mm256_set1_epi16(1);
+        let mut sum = _mm256_setzero_si256();
+        for (input, weights) in input.chunks_exact(32).zip(weights.chunks_exact(32)) {
+            let products = _mm256_maddubs_epi16(
+                _mm256_loadu_si256(input.as_ptr() as *const __m256i),
+                _mm256_loadu_si256(weights.as_ptr() as *const __m256i),
+            );
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(products, ones));
+        }
+        let sum = _mm_add_epi32(
+            _mm256_castsi256_si128(sum),
+            _mm256_extracti128_si256(sum, 1),
+        );
+        let sum = _mm_hadd_epi32(sum, sum);
+        _mm_cvtsi128_si32(_mm_hadd_epi32(sum, sum))
+    }
+}
@@ -0,0 +1,172 @@
+use crate::config;
+use crate::nnue::Network;
+#[cfg(feature = "tune")]
+use crate::params::EvalParams;
+use crate::table::TranspositionTable;
+use crate::uci_command::UciError;
+use std::sync::Arc;
+
+const MEGABYTE: usize = 1 << 20;
+
+pub const MIN_THREADS: u8 = 1;
+pub const MAX_THREADS: u8 = 64;
+pub const MIN_HASH_MB: u32 = 1;
+pub const MAX_HASH_MB: u32 = 16384;
+#[cfg(feature = "tune")]
+pub const MAX_EVAL_PARAM: i32 = 10000;
+
+// Settings the GUI can change with "setoption". Every option listed in
+// uci_option_lines has to be accepted by set_option and vice versa.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineOptions {
+    pub threads: u8,
+    pub hash_mb: u32,
+    pub chess960: bool,
+    // The network of "EvalFile" is only used while "UseNNUE" is on
+    pub use_nnue: bool,
+    pub network: Option<Arc<Network>>,
+    // Shared with the evaluators of the search threads
+    #[cfg(feature = "tune")]
+    pub eval_params: Arc<EvalParams>,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions {
+            threads: config::THREAD_COUNT,
+            hash_mb: default_hash_mb(),
+            chess960: false,
+            use_nnue: false,
+            network: None,
+            #[cfg(feature = "tune")]
+            eval_params: Arc::new(EvalParams::default()),
+        }
+    }
+}
+
+impl EngineOptions {
+    pub fn uci_option_lines(&self) -> Vec<String> {
+        let defaults = EngineOptions::default();
+
+        #[allow(unused_mut)]
+        let mut lines = vec![
+            format!(
+                "option name Threads type spin default {} min {MIN_THREADS} max {MAX_THREADS}",
+                defaults.threads
+            ),
+            format!(
+                "option name Hash type spin default {} min {MIN_HASH_MB} max {MAX_HASH_MB}",
+                defaults.hash_mb
+            ),
+            format!(
+                "option name UCI_Chess960 type check default {}",
+                defaults.chess960
+            ),
+            format!(
+                "option name UseNNUE type check default {}",
+                defaults.use_nnue
+            ),
+            "option name EvalFile type string default <empty>".to_string(),
+        ];
+
+        // "EvalParams" loads a whole parameter file, every single value of the
+        // parameters has its own option
+        #[cfg(feature = "tune")]
+        {
+            lines.push("option name EvalParams type string default <empty>".to_string());
+            for (name, value) in defaults.eval_params.scalars() {
+                lines.push(format!(
+                    "option name Eval_{name} type spin default {value} min -{MAX_EVAL_PARAM} max {MAX_EVAL_PARAM}"
+                ));
+            }
+        }
+
+        lines
+    }
+
+    pub fn set_option(&mut self, name: &str, value: Option<&str>) -> Result<(), UciError> {
+        // Option names are case insensitive in the protocol
+        match name.to_lowercase().as_str() {
+            "threads" => self.threads = parse_spin("Threads", value, MIN_THREADS, MAX_THREADS)?,
+            "hash" => self.hash_mb = parse_spin("Hash", value, MIN_HASH_MB, MAX_HASH_MB)?,
+            "uci_chess960" => self.chess960 = parse_check("UCI_Chess960", value)?,
+            "usennue" => self.use_nnue = parse_check("UseNNUE", value)?,
+            "evalfile" => {
+                self.network = match value {
+                    None | Some("<empty>") => None,
+                    Some(path) => Some(Arc::new(Network::load(path).map_err(|error| {
+                        UciError::InvalidValue("EvalFile", format!("{path}: {error}"))
+                    })?)),
+                };
+            }
+            #[cfg(feature = "tune")]
+            "evalparams" => {
+                let params = match value {
+                    None | Some("<empty>") => EvalParams::default(),
+                    Some(path) => EvalParams::load(path).map_err(|error| {
+                        UciError::InvalidValue("EvalParams", format!("{path}: {error}"))
+                    })?,
+                };
+                self.eval_params = Arc::new(params);
+            }
+            #[cfg(feature = "tune")]
+            option if option.starts_with("eval_") => {
+                let value = parse_spin("Eval", value, -MAX_EVAL_PARAM, MAX_EVAL_PARAM)?;
+                let mut params = (*self.eval_params).clone();
+                params
+                    .set_scalar(&option["eval_".len()..], value)
+                    .map_err(|_| UciError::UnknownOption(name.to_string()))?;
+                // A new Arc, the evaluators notice the change by the pointer
+                self.eval_params = Arc::new(params);
+            }
+            _ => return Err(UciError::UnknownOption(name.to_string())),
+        }
+        Ok(())
+    }
+
+    // The network the search threads evaluate with, None for the classical
+    // evaluation
+    pub fn active_network(&self) -> Option<Arc<Network>> {
+        self.network.clone().filter(|_| self.use_nnue)
+    }
+
+    // UseNNUE is on but there is no network to evaluate with
+    pub fn missing_network(&self) -> bool {
+        self.use_nnue && self.network.is_none()
+    }
+
+    pub fn hash_table_size(&self) -> u32 {
+        // The table is indexed with a bit mask, so its size has to be a power of two
+        let entries = (self.hash_mb as usize * MEGABYTE / TranspositionTable::entry_size()).max(1);
+        let power_of_two: usize = 1 << (usize::BITS - 1 - entries.leading_zeros());
+
+        power_of_two as u32
+    }
+}
+
+fn default_hash_mb() -> u32 {
+    (config::HASH_TABLE_SIZE as usize * TranspositionTable::entry_size() / MEGABYTE) as u32
+}
+
+fn parse_spin<T: std::str::FromStr + PartialOrd>(
+    name: &'static str,
+    value: Option<&str>,
+    min: T,
+    max: T,
+) -> Result<T, UciError> {
+    let value = value.ok_or(UciError::MissingArgument("value"))?;
+
+    match value.parse::<T>() {
+        Ok(parsed) if parsed >= min && parsed <= max => Ok(parsed),
+        _ => Err(UciError::InvalidValue(name, value.to_string())),
+    }
+}
+
+fn parse_check(name: &'static str, value: Option<&str>) -> Result<bool, UciError> {
+    match value {
+        Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(value) => Err(UciError::InvalidValue(name, value.to_string())),
+        None => Err(UciError::MissingArgument("value")),
+    }
+}
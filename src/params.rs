@@ -0,0 +1,411 @@
+use crate::eval::Score;
+
+// All weights of the evaluation. The built-in set is DEFAULT_PARAMS, which the
+// search always uses unless the "tune" feature is enabled. With the feature the
+// parameters can be loaded from a JSON file and changed with UCI options.
+//
+// The piece-square tables and the pawn shield are seen from white, the first
+// row is the first rank. Black uses them with the ranks mirrored.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "tune", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvalParams {
+    pub piece_values: [Score; 6],
+    pub psqt_mg: [[[i32; 8]; 8]; 6],
+    pub psqt_eg: [[[i32; 8]; 8]; 6],
+
+    pub pin_value: Score,
+    // Mobility only counts the squares not attacked by enemy pawns and not
+    // taken by the own pawns or king, the queen per two squares
+    pub bishop_mobility: Score,
+    pub rook_mobility: Score,
+    pub queen_mobility: Score,
+    pub in_check_penalty: Score,
+    pub invasion_bonus: Score,
+    pub side_to_move_bonus: Score,
+
+    // Middlegame only, by the square of the king and per own pawn next to it
+    pub pawn_shield: [[i32; 8]; 8],
+    // King safety: every attack on the king zone adds attack units by the type
+    // of the attacker, as does a safe check. The danger is units² / divisor up
+    // to the maximum, the endgame part is divided once more.
+    pub king_attack_weights: [i32; 6],
+    pub safe_check_weights: [i32; 6],
+    pub king_danger_divisor: i32,
+    pub king_danger_max: i32,
+    pub king_danger_endgame_divisor: i32,
+    pub king_open_file_penalty: Score,
+    pub king_half_open_file_penalty: Score,
+    // Enemy pawns on the files around the king, by their distance in ranks
+    pub pawn_storm_penalties: [Score; 8],
+
+    pub bishop_pair_bonus: Score,
+    pub rook_open_file_bonus: Score,
+    pub rook_semi_open_file_bonus: Score,
+    pub queen_open_file_bonus: Score,
+    pub queen_semi_open_file_bonus: Score,
+    pub rook_on_seventh_bonus: Score,
+    pub knight_outpost_bonus: Score,
+    pub bishop_outpost_bonus: Score,
+    pub bad_bishop_pawn_penalty: Score,
+    pub trapped_rook_penalty: Score,
+    pub trapped_bishop_penalty: Score,
+
+    pub threat_by_pawn: Score,
+    pub threat_by_minor: Score,
+    pub threat_by_rook: Score,
+    pub hanging_piece: Score,
+    pub pawn_push_threat: Score,
+
+    pub connected_pawn: Score,
+    pub isolated_pawn_penalty: Score,
+    pub doubled_pawn_penalty: Score,
+    pub backward_pawn_penalty: Score,
+    // Indexed by the rank seen from the pawn's side
+    pub passed_pawn_values: [Score; 8],
+    pub candidate_pawn_values: [Score; 8],
+    // Endgame only, weighs the king distances to the square in front of a
+    // passed pawn and the penalty when that square is occupied
+    pub passed_pawn_rank_weights: [i32; 8],
+    pub enemy_king_distance: i32,
+    pub own_king_distance: i32,
+    pub blocked_passed_pawn_penalty: i32,
+}
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        DEFAULT_PARAMS.clone()
+    }
+}
+
+impl EvalParams {
+    // The value of a piece on a square including its material
+    #[inline]
+    pub fn piece_square(&self, piece: usize, index: usize) -> Score {
+        let (rank, file) = (index / 8, index % 8);
+        self.piece_values[piece]
+            + Score::new(
+                self.psqt_mg[piece][rank][file],
+                self.psqt_eg[piece][rank][file],
+            )
+    }
+
+    #[inline]
+    pub fn king_danger(&self, units: i32) -> i32 {
+        (units * units / self.king_danger_divisor.max(1)).min(self.king_danger_max)
+    }
+}
+
+#[cfg(feature = "tune")]
+#[derive(Debug)]
+pub enum ParamsError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    UnknownParameter(String),
+}
+
+#[cfg(feature = "tune")]
+impl std::fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParamsError::Io(error) => write!(f, "{error}"),
+            ParamsError::Json(error) => write!(f, "{error}"),
+            ParamsError::UnknownParameter(name) => write!(f, "unknown parameter: {name}"),
+        }
+    }
+}
+
+#[cfg(feature = "tune")]
+impl EvalParams {
+    pub fn load(path: &str) -> Result<EvalParams, ParamsError> {
+        let json = std::fs::read_to_string(path).map_err(ParamsError::Io)?;
+        EvalParams::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<EvalParams, ParamsError> {
+        serde_json::from_str(json).map_err(ParamsError::Json)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    // The single values that can be set by name: "pin_value_mg" for the parts
+    // of a Score and "king_danger_max" for a plain number. Tables are only
+    // read from files.
+    pub fn scalars(&self) -> Vec<(String, i32)> {
+        let mut scalars = vec![];
+        let serde_json::Value::Object(fields) = serde_json::to_value(self).unwrap_or_default()
+        else {
+            return scalars;
+        };
+        for (name, value) in fields {
+            match value {
+                serde_json::Value::Number(number) => {
+                    scalars.push((name, number.as_i64().unwrap_or_default() as i32))
+                }
+                serde_json::Value::Object(score) => {
+                    for part in ["mg", "eg"] {
+                        let value = score.get(part).and_then(|value| value.as_i64());
+                        scalars.push((format!("{name}_{part}"), value.unwrap_or_default() as i32));
+                    }
+                }
+                _ => {}
+            }
+        }
+        scalars
+    }
+
+    pub fn set_scalar(&mut self, name: &str, value: i32) -> Result<(), ParamsError> {
+        let unknown = || ParamsError::UnknownParameter(name.to_string());
+        let mut fields = serde_json::to_value(&*self).map_err(ParamsError::Json)?;
+
+        let field = match fields.get_mut(name) {
+            Some(field) if field.is_number() => field,
+            _ => {
+                let (score, part) = name.rsplit_once('_').ok_or_else(unknown)?;
+                fields
+                    .get_mut(score)
+                    .filter(|field| field.is_object())
+                    .and_then(|field| field.get_mut(part))
+                    .ok_or_else(unknown)?
+            }
+        };
+        *field = value.into();
+
+        *self = serde_json::from_value(fields).map_err(ParamsError::Json)?;
+        Ok(())
+    }
+}
+
+pub const DEFAULT_PARAMS: EvalParams = EvalParams {
+    piece_values: [
+        Score::new(80, 100),
+        Score::new(300, 290),
+        Score::new(310, 300),
+        Score::new(450, 480),
+        Score::new(900, 900),
+        Score::new(0, 0),
+    ],
+    #[rustfmt::skip]
+    psqt_mg: [
+        // Pawn
+        [
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 0,  2,  2, -6, -6,  2,  2,  0],
+            [ 2,  0, -2,  3,  3, -2,  0,  2],
+            [ 0,  0,  3, 10, 10,  3,  0,  0],
+            [ 2,  2,  5, 12, 12,  5,  2,  2],
+            [ 6,  6, 10, 15, 15, 10,  6,  6],
+            [20, 20, 20, 20, 20, 20, 20, 20],
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+        ],
+        // Knight
+        [
+            [-20,-10, -6, -6, -6, -6,-10,-20],
+            [-10, -4,  0,  2,  2,  0, -4,-10],
+            [ -6,  2,  6,  6,  6,  6,  2, -6],
+            [ -6,  0,  6, 10, 10,  6,  0, -6],
+            [ -6,  2,  6, 10, 10,  6,  2, -6],
+            [ -6,  0,  6,  6,  6,  6,  0, -6],
+            [-10, -4,  0,  0,  0,  0, -4,-10],
+            [-20,-10, -6, -6, -6, -6,-10,-20],
+        ],
+        // Bishop
+        [
+            [-8, -4, -6, -4, -4, -6, -4, -8],
+            [-4,  6,  2,  2,  2,  2,  6, -4],
+            [-4,  4,  4,  4,  4,  4,  4, -4],
+            [-4,  2,  6,  4,  4,  6,  2, -4],
+            [-4,  4,  4,  6,  6,  4,  4, -4],
+            [-4,  2,  4,  4,  4,  4,  2, -4],
+            [-4,  0,  0,  0,  0,  0,  0, -4],
+            [-8, -4, -4, -4, -4, -4, -4, -8],
+        ],
+        // Rook
+        [
+            [-2, -1,  2,  3,  3,  2, -1, -2],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-2,  0,  2,  2,  2,  2,  0, -2],
+            [ 8, 10, 10, 10, 10, 10, 10,  8],
+            [ 4,  4,  4,  4,  4,  4,  4,  4],
+        ],
+        // Queen
+        [
+            [-6, -4, -2,  0,  0, -2, -4, -6],
+            [-4,  0,  2,  2,  2,  2,  0, -4],
+            [-2,  2,  2,  2,  2,  2,  2, -2],
+            [ 0,  0,  2,  2,  2,  2,  0,  0],
+            [-2,  0,  2,  2,  2,  2,  0, -2],
+            [-2,  0,  0,  0,  0,  0,  0, -2],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-6, -4, -4, -2, -2, -4, -4, -6],
+        ],
+        // King
+        [
+            [ 10, 16,  6, -4, -4,  6, 16, 10],
+            [  4,  4, -6,-12,-12, -6,  4,  4],
+            [-10,-14,-16,-20,-20,-16,-14,-10],
+            [-20,-24,-26,-30,-30,-26,-24,-20],
+            [-26,-30,-30,-36,-36,-30,-30,-26],
+            [-30,-30,-30,-36,-36,-30,-30,-30],
+            [-30,-30,-30,-36,-36,-30,-30,-30],
+            [-30,-30,-30,-36,-36,-30,-30,-30],
+        ],
+    ],
+    #[rustfmt::skip]
+    psqt_eg: [
+        // Pawn
+        [
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 2,  2,  2,  2,  2,  2,  2,  2],
+            [ 5,  5,  5,  5,  5,  5,  5,  5],
+            [10, 10, 10, 10, 10, 10, 10, 10],
+            [20, 20, 20, 20, 20, 20, 20, 20],
+            [35, 35, 35, 35, 35, 35, 35, 35],
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+        ],
+        // Knight
+        [
+            [-16,-10, -6, -4, -4, -6,-10,-16],
+            [-10, -4,  0,  2,  2,  0, -4,-10],
+            [ -6,  0,  4,  6,  6,  4,  0, -6],
+            [ -4,  2,  6,  8,  8,  6,  2, -4],
+            [ -4,  2,  6,  8,  8,  6,  2, -4],
+            [ -6,  0,  4,  6,  6,  4,  0, -6],
+            [-10, -4,  0,  2,  2,  0, -4,-10],
+            [-16,-10, -6, -4, -4, -6,-10,-16],
+        ],
+        // Bishop
+        [
+            [-6, -4, -4, -2, -2, -4, -4, -6],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-4,  0,  2,  2,  2,  2,  0, -4],
+            [-2,  0,  2,  4,  4,  2,  0, -2],
+            [-2,  0,  2,  4,  4,  2,  0, -2],
+            [-4,  0,  2,  2,  2,  2,  0, -4],
+            [-4, -2,  0,  0,  0,  0, -2, -4],
+            [-6, -4, -4, -2, -2, -4, -4, -6],
+        ],
+        // Rook
+        [
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 0,  0,  0,  0,  0,  0,  0,  0],
+            [ 2,  2,  2,  2,  2,  2,  2,  2],
+            [ 2,  2,  2,  2,  2,  2,  2,  2],
+            [ 2,  2,  2,  2,  2,  2,  2,  2],
+            [ 6,  6,  6,  6,  6,  6,  6,  6],
+            [ 4,  4,  4,  4,  4,  4,  4,  4],
+        ],
+        // Queen
+        [
+            [-10, -6, -4, -4, -4, -4, -6,-10],
+            [ -6, -2,  0,  0,  0,  0, -2, -6],
+            [ -4,  0,  4,  4,  4,  4,  0, -4],
+            [ -4,  0,  4,  8,  8,  4,  0, -4],
+            [ -4,  0,  4,  8,  8,  4,  0, -4],
+            [ -4,  0,  4,  4,  4,  4,  0, -4],
+            [ -6, -2,  0,  0,  0,  0, -2, -6],
+            [-10, -6, -4, -4, -4, -4, -6,-10],
+        ],
+        // King
+        [
+            [-20,-14,-10, -8, -8,-10,-14,-20],
+            [-14, -4,  0,  2,  2,  0, -4,-14],
+            [-10,  0,  8, 10, 10,  8,  0,-10],
+            [ -8,  2, 10, 14, 14, 10,  2, -8],
+            [ -8,  2, 10, 14, 14, 10,  2, -8],
+            [-10,  0,  8, 10, 10,  8,  0,-10],
+            [-14, -4,  0,  2,  2,  0, -4,-14],
+            [-20,-14,-10, -8, -8,-10,-14,-20],
+        ],
+    ],
+
+    pin_value: Score::new(5, 5),
+    bishop_mobility: Score::new(2, 3),
+    rook_mobility: Score::new(1, 2),
+    queen_mobility: Score::new(1, 1),
+    in_check_penalty: Score::new(30, 20),
+    invasion_bonus: Score::new(3, 2),
+    side_to_move_bonus: Score::new(1, 1),
+
+    #[rustfmt::skip]
+    pawn_shield: [
+        [10, 10, 10,  0,  0, 10, 10, 10],
+        [ 5,  5,  0,  0,  0,  0,  5,  5],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+        [ 0,  0,  0,  0,  0,  0,  0,  0],
+    ],
+    king_attack_weights: [0, 2, 2, 3, 5, 0],
+    safe_check_weights: [0, 6, 4, 6, 8, 0],
+    king_danger_divisor: 6,
+    king_danger_max: 500,
+    king_danger_endgame_divisor: 8,
+    king_open_file_penalty: Score::new(-20, -4),
+    king_half_open_file_penalty: Score::new(-10, -2),
+    pawn_storm_penalties: [
+        Score::new(0, 0),
+        Score::new(-4, 0),
+        Score::new(-16, 0),
+        Score::new(-10, 0),
+        Score::new(-5, 0),
+        Score::new(0, 0),
+        Score::new(0, 0),
+        Score::new(0, 0),
+    ],
+
+    bishop_pair_bonus: Score::new(30, 50),
+    rook_open_file_bonus: Score::new(20, 10),
+    rook_semi_open_file_bonus: Score::new(10, 5),
+    queen_open_file_bonus: Score::new(4, 4),
+    queen_semi_open_file_bonus: Score::new(2, 2),
+    rook_on_seventh_bonus: Score::new(15, 25),
+    knight_outpost_bonus: Score::new(20, 10),
+    bishop_outpost_bonus: Score::new(10, 5),
+    bad_bishop_pawn_penalty: Score::new(-2, -4),
+    trapped_rook_penalty: Score::new(-40, -10),
+    trapped_bishop_penalty: Score::new(-80, -80),
+
+    threat_by_pawn: Score::new(40, 30),
+    threat_by_minor: Score::new(25, 30),
+    threat_by_rook: Score::new(25, 30),
+    hanging_piece: Score::new(20, 15),
+    pawn_push_threat: Score::new(15, 10),
+
+    connected_pawn: Score::new(2, 4),
+    isolated_pawn_penalty: Score::new(-8, -12),
+    doubled_pawn_penalty: Score::new(-10, -20),
+    backward_pawn_penalty: Score::new(-6, -10),
+    passed_pawn_values: [
+        Score::new(0, 0),
+        Score::new(5, 10),
+        Score::new(5, 15),
+        Score::new(10, 25),
+        Score::new(20, 45),
+        Score::new(35, 75),
+        Score::new(60, 120),
+        Score::new(0, 0),
+    ],
+    candidate_pawn_values: [
+        Score::new(0, 0),
+        Score::new(2, 4),
+        Score::new(2, 6),
+        Score::new(4, 10),
+        Score::new(8, 18),
+        Score::new(12, 28),
+        Score::new(0, 0),
+        Score::new(0, 0),
+    ],
+    passed_pawn_rank_weights: [0, 0, 0, 1, 2, 3, 5, 0],
+    enemy_king_distance: 4,
+    own_king_distance: 2,
+    blocked_passed_pawn_penalty: 6,
+};
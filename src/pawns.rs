@@ -0,0 +1,273 @@
+use chess::{BitBoard, Board, Color, Piece, Square, EMPTY};
+
+use crate::eval::Score;
+use crate::params::EvalParams;
+
+// Pawn structure evaluation. Everything that only depends on the pawns is
+// cached in a PawnTable keyed by a Zobrist hash of the pawns alone, the passed
+// pawn terms that depend on the kings and the other pieces are added on top.
+
+const PAWN_TABLE_SIZE: usize = 1 << 14;
+
+#[derive(Clone, Copy)]
+pub struct PawnEntry {
+    key: u64,
+    // White minus black
+    pub score: Score,
+    pub passed: [BitBoard; 2],
+}
+
+impl Default for PawnEntry {
+    // A position without pawns has the key 0 and no pawn score, so the empty
+    // entries are already correct for it
+    fn default() -> PawnEntry {
+        PawnEntry {
+            key: 0,
+            score: Score::new(0, 0),
+            passed: [EMPTY; 2],
+        }
+    }
+}
+
+// Kept by every search thread, so it needs no locking
+pub struct PawnTable {
+    entries: Vec<PawnEntry>,
+}
+
+impl Default for PawnTable {
+    fn default() -> PawnTable {
+        PawnTable::new()
+    }
+}
+
+impl PawnTable {
+    pub fn new() -> PawnTable {
+        PawnTable {
+            entries: vec![PawnEntry::default(); PAWN_TABLE_SIZE],
+        }
+    }
+
+    #[inline]
+    pub fn probe(&mut self, board: &Board, params: &EvalParams) -> PawnEntry {
+        let key = pawn_hash(board);
+        let entry = &mut self.entries[key as usize & (PAWN_TABLE_SIZE - 1)];
+        if entry.key != key {
+            *entry = evaluate_pawns(board, params);
+        }
+        *entry
+    }
+}
+
+// Zobrist keys for a pawn of either color on every square
+const PAWN_KEYS: [[u64; 64]; 2] = {
+    let mut keys = [[0; 64]; 2];
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut index = 0;
+    while index < 128 {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        keys[index / 64][index % 64] = value ^ (value >> 31);
+        index += 1;
+    }
+    keys
+};
+
+#[inline]
+pub fn pawn_hash(board: &Board) -> u64 {
+    let mut hash = 0;
+    for color in [Color::White, Color::Black] {
+        for square in board.pieces(Piece::Pawn) & board.color_combined(color) {
+            hash ^= PAWN_KEYS[color.to_index()][square.to_index()];
+        }
+    }
+    hash
+}
+
+const FILE_A: u64 = 0x0101_0101_0101_0101;
+
+// Squares in front of a square on the same file, for either color
+const FORWARD_FILE: [[u64; 64]; 2] = {
+    let mut masks = [[0; 64]; 2];
+    let mut square = 0;
+    while square < 64 {
+        let file = FILE_A << (square % 8);
+        let rank = square / 8;
+        masks[0][square] = if rank == 7 {
+            0
+        } else {
+            file & (u64::MAX << ((rank + 1) * 8))
+        };
+        masks[1][square] = file & ((1u64 << (rank * 8)) - 1);
+        square += 1;
+    }
+    masks
+};
+
+const ADJACENT_FILES: [u64; 8] = {
+    let mut masks = [0; 8];
+    let mut file = 0;
+    while file < 8 {
+        if file > 0 {
+            masks[file] |= FILE_A << (file - 1);
+        }
+        if file < 7 {
+            masks[file] |= FILE_A << (file + 1);
+        }
+        file += 1;
+    }
+    masks
+};
+
+#[inline]
+fn forward_file(color: Color, square: Square) -> BitBoard {
+    BitBoard(FORWARD_FILE[color.to_index()][square.to_index()])
+}
+
+#[inline]
+fn adjacent_files(square: Square) -> BitBoard {
+    BitBoard(ADJACENT_FILES[square.get_file().to_index()])
+}
+
+// The squares on the adjacent files in front of the square
+#[inline]
+pub fn attack_span(color: Color, square: Square) -> BitBoard {
+    let mut span = EMPTY;
+    for neighbour in adjacent_files(square) & chess::get_rank(square.get_rank()) {
+        span |= forward_file(color, neighbour);
+    }
+    span
+}
+
+// The squares on the adjacent files on the rank of the square and behind it
+#[inline]
+fn support_span(color: Color, square: Square) -> BitBoard {
+    adjacent_files(square) & !attack_span(color, square)
+}
+
+#[inline]
+pub fn relative_rank(color: Color, square: Square) -> usize {
+    match color {
+        Color::White => square.get_rank().to_index(),
+        Color::Black => 7 - square.get_rank().to_index(),
+    }
+}
+
+pub fn is_passed(board: &Board, color: Color, square: Square) -> bool {
+    let own_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let front = forward_file(color, square);
+
+    // The rear pawn of doubled pawns is not passed
+    (front | attack_span(color, square)) & enemy_pawns == EMPTY && front & own_pawns == EMPTY
+}
+
+// The cached part of the pawn evaluation
+pub fn evaluate_pawns(board: &Board, params: &EvalParams) -> PawnEntry {
+    let mut entry = PawnEntry {
+        key: pawn_hash(board),
+        ..PawnEntry::default()
+    };
+    let white = evaluate_pawn_side(board, Color::White, &mut entry, params);
+    let black = evaluate_pawn_side(board, Color::Black, &mut entry, params);
+    entry.score = white - black;
+    entry
+}
+
+fn evaluate_pawn_side(
+    board: &Board,
+    color: Color,
+    entry: &mut PawnEntry,
+    params: &EvalParams,
+) -> Score {
+    let own_pawns = board.pieces(Piece::Pawn) & board.color_combined(color);
+    let enemy_pawns = board.pieces(Piece::Pawn) & board.color_combined(!color);
+    let mut score = Score::new(0, 0);
+
+    for square in own_pawns {
+        let rank = relative_rank(color, square);
+        let front = forward_file(color, square);
+        let neighbours = own_pawns & adjacent_files(square);
+
+        // Own pawns defending this one stand where an enemy pawn on this
+        // square would attack
+        score += params.connected_pawn
+            * chess::get_pawn_attacks(square, !color, own_pawns).popcnt() as i32;
+
+        if front & own_pawns != EMPTY {
+            score += params.doubled_pawn_penalty;
+        }
+
+        if is_passed(board, color, square) {
+            entry.passed[color.to_index()] |= BitBoard::from_square(square);
+            score += params.passed_pawn_values[rank];
+        } else if front & enemy_pawns == EMPTY {
+            // Candidate: the file is open and the own pawns that can support
+            // the advance outnumber the enemy pawns in its way
+            let helpers = (own_pawns & support_span(color, square)).popcnt();
+            let sentries = (enemy_pawns & attack_span(color, square)).popcnt();
+            if helpers >= sentries {
+                score += params.candidate_pawn_values[rank];
+            }
+        }
+
+        if neighbours == EMPTY {
+            score += params.isolated_pawn_penalty;
+        } else if neighbours & support_span(color, square) == EMPTY {
+            // Backward: no pawn can support it and the square in front is
+            // controlled by an enemy pawn
+            if let Some(stop) = stop_square(color, square) {
+                if chess::get_pawn_attacks(stop, color, enemy_pawns) != EMPTY {
+                    score += params.backward_pawn_penalty;
+                }
+            }
+        }
+    }
+    score
+}
+
+// The endgame terms of the passed pawns of one side, they depend on the kings
+// and the pieces so they are not cached
+pub fn evaluate_passed_pawns(
+    board: &Board,
+    color: Color,
+    passed: BitBoard,
+    params: &EvalParams,
+) -> Score {
+    let own_king = board.king_square(color);
+    let enemy_king = board.king_square(!color);
+    let mut endgame = 0;
+
+    for square in passed {
+        let weight = params.passed_pawn_rank_weights[relative_rank(color, square)];
+        let Some(stop) = stop_square(color, square) else {
+            continue;
+        };
+
+        endgame += weight
+            * (params.enemy_king_distance * distance(enemy_king, stop)
+                - params.own_king_distance * distance(own_king, stop));
+
+        if board.piece_on(stop).is_some() {
+            endgame -= weight * params.blocked_passed_pawn_penalty;
+        }
+    }
+    Score::new(0, endgame)
+}
+
+#[inline]
+fn stop_square(color: Color, square: Square) -> Option<Square> {
+    match color {
+        Color::White => square.up(),
+        Color::Black => square.down(),
+    }
+}
+
+#[inline]
+fn distance(from: Square, to: Square) -> i32 {
+    let files = (from.get_file().to_index() as i32 - to.get_file().to_index() as i32).abs();
+    let ranks = (from.get_rank().to_index() as i32 - to.get_rank().to_index() as i32).abs();
+    files.max(ranks)
+}
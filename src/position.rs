@@ -0,0 +1,495 @@
+use chess::{
+    Board, BoardBuilder, BoardStatus, CastleRights, ChessMove, Color, MoveGen, Piece, EMPTY,
+};
+
+use crate::chess960::{castle_side, castling_moves, CastlingRights};
+use crate::uci_command::UciError;
+
+// Everything that is needed to take back a move
+#[derive(Clone, Debug, PartialEq)]
+struct HistoryEntry {
+    board: Board,
+    castling: CastlingRights,
+    chess_move: ChessMove,
+    halfmove_clock: u32,
+}
+
+// A game from a starting position with the full move history. The hashes of
+// all positions of the game are kept for repetition detection in the search,
+// hash_history[0] is the starting position, the last entry the current one.
+//
+// In Chess960 mode the board has no castle rights of its own, castling is
+// handled with the rights in `castling` (see chess960.rs). In standard mode
+// `castling` mirrors the castle rights of the board.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Position {
+    pub board: Board,
+    pub hash_history: Vec<u64>,
+    pub halfmove_clock: u32,
+    pub castling: CastlingRights,
+    pub chess960: bool,
+    start_board: Board,
+    start_castling: CastlingRights,
+    start_halfmove_clock: u32,
+    start_fullmove_number: u32,
+    history: Vec<HistoryEntry>,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position::new(Board::default())
+    }
+}
+
+impl Position {
+    pub fn new(board: Board) -> Position {
+        Position::with_clocks(board, 0, 1)
+    }
+
+    pub fn with_clocks(board: Board, halfmove_clock: u32, fullmove_number: u32) -> Position {
+        let castling = CastlingRights::from_board(&board);
+
+        Position {
+            board,
+            hash_history: vec![board.get_hash()],
+            halfmove_clock,
+            castling,
+            chess960: false,
+            start_board: board,
+            start_castling: castling,
+            start_halfmove_clock: halfmove_clock,
+            start_fullmove_number: fullmove_number.max(1),
+            history: vec![],
+        }
+    }
+
+    // A Chess960 position, the castle rights of the board are ignored
+    pub fn with_castling(
+        board: Board,
+        castling: CastlingRights,
+        halfmove_clock: u32,
+        fullmove_number: u32,
+    ) -> Result<Position, UciError> {
+        let mut position = Position::with_clocks(
+            with_castle_rights(&board, [CastleRights::NoRights; 2])?,
+            halfmove_clock,
+            fullmove_number,
+        );
+        position.castling = castling;
+        position.start_castling = castling;
+        position.chess960 = true;
+        position.hash_history = vec![position.hash()];
+        Ok(position)
+    }
+
+    // Switches between standard chess and Chess960 castling. The starting
+    // position is converted and the moves are replayed, which fails if the
+    // castling rights or moves can not be expressed in standard chess.
+    pub fn set_chess960(&mut self, enabled: bool) -> Result<(), UciError> {
+        if self.chess960 == enabled {
+            return Ok(());
+        }
+
+        let mut converted = match enabled {
+            true => Position::with_castling(
+                self.start_board,
+                self.start_castling,
+                self.start_halfmove_clock,
+                self.start_fullmove_number,
+            )?,
+            false => {
+                let standard_rights = self
+                    .start_castling
+                    .to_standard(&self.start_board)
+                    .ok_or(UciError::InvalidCastlingRights(self.start_fen()))?;
+                Position::with_clocks(
+                    with_castle_rights(&self.start_board, standard_rights)?,
+                    self.start_halfmove_clock,
+                    self.start_fullmove_number,
+                )
+            }
+        };
+
+        let moves: Vec<ChessMove> = self.moves().collect();
+        converted.push_moves(&moves)?;
+        *self = converted;
+        Ok(())
+    }
+
+    pub fn start_board(&self) -> Board {
+        self.start_board
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        let plies = self.history.len() as u32 + self.start_color_offset();
+        self.start_fullmove_number + plies / 2
+    }
+
+    pub fn moves(&self) -> impl Iterator<Item = ChessMove> + '_ {
+        self.history.iter().map(|entry| entry.chess_move)
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    pub fn push(&mut self, chess_move: ChessMove) -> Result<(), UciError> {
+        let (next_board, next_castling) = self
+            .play(chess_move)
+            .ok_or(UciError::IllegalMove(chess_move.to_string()))?;
+
+        // Castling moves a king onto its own rook, which is not a capture
+        let irreversible = self.board.piece_on(chess_move.get_source()) == Some(Piece::Pawn)
+            || self.board.color_on(chess_move.get_dest()) == Some(!self.board.side_to_move());
+
+        self.history.push(HistoryEntry {
+            board: self.board,
+            castling: self.castling,
+            chess_move,
+            halfmove_clock: self.halfmove_clock,
+        });
+
+        self.board = next_board;
+        self.castling = next_castling;
+        self.hash_history.push(self.hash());
+        self.halfmove_clock = match irreversible {
+            true => 0,
+            false => self.halfmove_clock + 1,
+        };
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<ChessMove> {
+        let entry = self.history.pop()?;
+
+        self.board = entry.board;
+        self.castling = entry.castling;
+        self.halfmove_clock = entry.halfmove_clock;
+        self.hash_history.pop();
+
+        Some(entry.chess_move)
+    }
+
+    // The board and castling rights after a move, None if the move is illegal
+    fn play(&self, chess_move: ChessMove) -> Option<(Board, CastlingRights)> {
+        if self.chess960 && castle_side(&self.board, &self.castling, chess_move).is_some() {
+            return castling_moves(&self.board, &self.castling)
+                .into_iter()
+                .find(|(castling_move, _)| *castling_move == chess_move)
+                .map(|(_, board)| {
+                    (
+                        board,
+                        castling_after(&self.board, &self.castling, chess_move),
+                    )
+                });
+        }
+
+        if !self.board.legal(chess_move) {
+            return None;
+        }
+        let next_board = self.board.make_move_new(chess_move);
+
+        Some((
+            next_board,
+            castling_after(&self.board, &self.castling, chess_move),
+        ))
+    }
+
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        let mut moves: Vec<ChessMove> = MoveGen::new_legal(&self.board).collect();
+        let castling = castling_moves(&self.board, &self.search_castling());
+        moves.extend(castling.into_iter().map(|(chess_move, _)| chess_move));
+        moves
+    }
+
+    // The castling rights the search has to track itself, the rights of a
+    // standard chess position are part of its board
+    pub fn search_castling(&self) -> CastlingRights {
+        match self.chess960 {
+            true => self.castling,
+            false => CastlingRights::default(),
+        }
+    }
+
+    // The board hash, in Chess960 combined with the castling rights
+    pub fn hash(&self) -> u64 {
+        self.board.get_hash() ^ self.search_castling().hash()
+    }
+
+    pub fn perft(&self, depth: u8) -> u64 {
+        perft(&self.board, &self.castling, self.chess960, depth)
+    }
+
+    pub fn push_moves(&mut self, moves: &[ChessMove]) -> Result<(), UciError> {
+        for (n_pushed, chess_move) in moves.iter().enumerate() {
+            if let Err(error) = self.push(*chess_move) {
+                for _ in 0..n_pushed {
+                    self.pop();
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    // Brings the position to `start` followed by `moves`. GUIs send the whole game
+    // with every "position" command, so only the moves that differ from the
+    // current history are taken back and played. On an illegal move the
+    // position is left unchanged.
+    pub fn update(&mut self, start: &Position, moves: &[ChessMove]) -> Result<(), UciError> {
+        if !self.same_start(start) {
+            let mut new_position = start.clone();
+            new_position.push_moves(moves)?;
+            *self = new_position;
+            return Ok(());
+        }
+
+        let common_moves = self
+            .moves()
+            .zip(moves.iter())
+            .take_while(|(played, new)| played == *new)
+            .count();
+
+        let mut taken_back = vec![];
+        while self.len() > common_moves {
+            taken_back.extend(self.pop());
+        }
+
+        if let Err(error) = self.push_moves(&moves[common_moves..]) {
+            for chess_move in taken_back.iter().rev() {
+                let _ = self.push(*chess_move);
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Positions before the last capture or pawn move can not be repeated
+    pub fn repetition_hashes(&self) -> &[u64] {
+        let reversible = (self.halfmove_clock as usize).min(self.hash_history.len() - 1);
+        &self.hash_history[self.hash_history.len() - 1 - reversible..]
+    }
+
+    pub fn fen(&self) -> String {
+        format_fen(
+            &self.board,
+            &self.castling,
+            self.chess960,
+            self.halfmove_clock,
+            self.fullmove_number(),
+        )
+    }
+
+    fn start_fen(&self) -> String {
+        format_fen(
+            &self.start_board,
+            &self.start_castling,
+            self.chess960,
+            self.start_halfmove_clock,
+            self.start_fullmove_number,
+        )
+    }
+
+    pub fn pgn(&self) -> String {
+        let mut pgn = String::new();
+
+        if self.chess960 {
+            pgn.push_str("[Variant \"Chess960\"]\n");
+        }
+        if self.chess960 || self.start_board != Board::default() || self.start_fullmove_number != 1
+        {
+            pgn.push_str("[SetUp \"1\"]\n");
+            pgn.push_str(&format!("[FEN \"{}\"]\n", self.start_fen()));
+        }
+        if !pgn.is_empty() {
+            pgn.push('\n');
+        }
+
+        let mut movetext = vec![];
+        for (ply, entry) in self.history.iter().enumerate() {
+            let move_number_ply = ply as u32 + self.start_color_offset();
+            let move_number = self.start_fullmove_number + move_number_ply / 2;
+
+            if entry.board.side_to_move() == chess::Color::White {
+                movetext.push(format!("{move_number}."));
+            } else if movetext.is_empty() {
+                movetext.push(format!("{move_number}..."));
+            }
+            let next_board = match self.history.get(ply + 1) {
+                Some(next_entry) => next_entry.board,
+                None => self.board,
+            };
+            movetext.push(san(&entry.board, entry.chess_move, &next_board));
+        }
+        movetext.push("*".to_string());
+
+        pgn.push_str(&movetext.join(" "));
+        pgn
+    }
+
+    fn same_start(&self, other: &Position) -> bool {
+        self.chess960 == other.chess960
+            && self.start_castling == other.start_castling
+            && self.start_board == other.start_board
+            && self.start_halfmove_clock == other.start_halfmove_clock
+            && self.start_fullmove_number == other.start_fullmove_number
+    }
+
+    fn start_color_offset(&self) -> u32 {
+        match self.start_board.side_to_move() {
+            chess::Color::White => 0,
+            chess::Color::Black => 1,
+        }
+    }
+}
+
+fn castling_after(
+    board: &Board,
+    castling: &CastlingRights,
+    chess_move: ChessMove,
+) -> CastlingRights {
+    let mut next_castling = *castling;
+    next_castling.update(board, chess_move);
+    next_castling
+}
+
+fn with_castle_rights(board: &Board, rights: [CastleRights; 2]) -> Result<Board, UciError> {
+    let mut builder = BoardBuilder::from(board);
+    builder
+        .castle_rights(Color::White, rights[0])
+        .castle_rights(Color::Black, rights[1]);
+
+    Board::try_from(builder).map_err(|_| UciError::InvalidCastlingRights(board.to_string()))
+}
+
+fn format_fen(
+    board: &Board,
+    castling: &CastlingRights,
+    chess960: bool,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+) -> String {
+    let board_fen = board.to_string();
+    let mut fields: Vec<String> = board_fen.split(' ').take(4).map(String::from).collect();
+
+    if chess960 {
+        fields[2] = castling.to_fen_field(board);
+    }
+
+    format!("{} {halfmove_clock} {fullmove_number}", fields.join(" "))
+}
+
+fn perft(board: &Board, castling: &CastlingRights, chess960: bool, depth: u8) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    for chess_move in MoveGen::new_legal(board) {
+        let next_castling = castling_after(board, castling, chess_move);
+        nodes += perft(
+            &board.make_move_new(chess_move),
+            &next_castling,
+            chess960,
+            depth - 1,
+        );
+    }
+
+    if chess960 {
+        for (chess_move, next_board) in castling_moves(board, castling) {
+            let next_castling = castling_after(board, castling, chess_move);
+            nodes += perft(&next_board, &next_castling, chess960, depth - 1);
+        }
+    }
+    nodes
+}
+
+pub fn to_san(board: &Board, chess_move: ChessMove) -> String {
+    san(board, chess_move, &board.make_move_new(chess_move))
+}
+
+fn san(board: &Board, chess_move: ChessMove, next_board: &Board) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board.piece_on(source).unwrap_or(Piece::Pawn);
+    let is_castling = piece == Piece::King
+        && (board.color_on(dest) == Some(board.side_to_move())
+            || source
+                .get_file()
+                .to_index()
+                .abs_diff(dest.get_file().to_index())
+                == 2);
+    let is_capture = board.color_on(dest) == Some(!board.side_to_move())
+        || (piece == Piece::Pawn && source.get_file() != dest.get_file());
+
+    let mut san = match piece {
+        Piece::King if is_castling => {
+            match dest.get_file().to_index() > source.get_file().to_index() {
+                true => "O-O".to_string(),
+                false => "O-O-O".to_string(),
+            }
+        }
+        Piece::Pawn => {
+            let mut san = String::new();
+            if is_capture {
+                san.push_str(&source.to_string()[0..1]);
+                san.push('x');
+            }
+            san.push_str(&dest.to_string());
+            if let Some(promotion) = chess_move.get_promotion() {
+                san.push('=');
+                san.push_str(&promotion.to_string(chess::Color::White));
+            }
+            san
+        }
+        _ => {
+            let mut san = piece.to_string(chess::Color::White);
+            san.push_str(&disambiguation(board, chess_move, piece));
+            if is_capture {
+                san.push('x');
+            }
+            san.push_str(&dest.to_string());
+            san
+        }
+    };
+
+    if next_board.status() == BoardStatus::Checkmate {
+        san.push('#');
+    } else if *next_board.checkers() != EMPTY {
+        san.push('+');
+    }
+    san
+}
+
+fn disambiguation(board: &Board, chess_move: ChessMove, piece: Piece) -> String {
+    let source = chess_move.get_source();
+    let others: Vec<ChessMove> = MoveGen::new_legal(board)
+        .filter(|other| {
+            other.get_dest() == chess_move.get_dest()
+                && other.get_source() != source
+                && board.piece_on(other.get_source()) == Some(piece)
+        })
+        .collect();
+
+    let square = source.to_string();
+    if others.is_empty() {
+        String::new()
+    } else if others
+        .iter()
+        .all(|other| other.get_source().get_file() != source.get_file())
+    {
+        square[0..1].to_string()
+    } else if others
+        .iter()
+        .all(|other| other.get_source().get_rank() != source.get_rank())
+    {
+        square[1..2].to_string()
+    } else {
+        square
+    }
+}
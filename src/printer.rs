@@ -0,0 +1,158 @@
+use chess::ChessMove;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::io::Write;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+
+use crate::config;
+use crate::search::{null_move, PositionScore, SearchInfo, SearchOutcome};
+
+// All output of the engine goes through a single channel, so the order in which
+// events are sent is the order in which lines are printed. Search threads send
+// Info events directly, the final BestMove is sent by the uci loop after the
+// search threads have been joined, so it is always the last line of a search.
+pub enum OutputEvent {
+    Info(SearchInfo),
+    BestMove(SearchOutcome),
+    Raw(String),
+    Shutdown,
+}
+
+pub struct Printer {
+    sender: Sender<OutputEvent>,
+    handle: JoinHandle<()>,
+}
+
+impl Printer {
+    pub fn start<W: Write + Send + 'static>(output: W) -> Printer {
+        let (sender, receiver) = channel();
+        let handle = thread::spawn(move || printing_loop(receiver, output));
+
+        Printer { sender, handle }
+    }
+
+    pub fn sender(&self) -> Sender<OutputEvent> {
+        self.sender.clone()
+    }
+
+    pub fn print(&self, text: &str) {
+        let _ = self.sender.send(OutputEvent::Raw(text.to_string()));
+    }
+
+    pub fn bestmove(&self, outcome: SearchOutcome) {
+        let _ = self.sender.send(OutputEvent::BestMove(outcome));
+    }
+
+    pub fn stop(self) {
+        let _ = self.sender.send(OutputEvent::Shutdown);
+        let _ = self.handle.join();
+    }
+}
+
+// Forwards log records to the GUI as "info string" lines while debug mode is
+// switched on with "debug on". The logger is installed once per process, the
+// sender is replaced whenever a new printer is attached to it.
+struct InfoStringLogger {
+    sender: Mutex<Option<Sender<OutputEvent>>>,
+}
+
+static LOGGER: InfoStringLogger = InfoStringLogger {
+    sender: Mutex::new(None),
+};
+
+impl Log for InfoStringLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                let message = record.args().to_string();
+                for line in message.lines().filter(|line| !line.trim().is_empty()) {
+                    let _ = sender.send(OutputEvent::Raw(format!("info string {line}")));
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn attach_logger(printer: &Printer) {
+    let _ = log::set_logger(&LOGGER);
+    if let Ok(mut sender) = LOGGER.sender.lock() {
+        *sender = Some(printer.sender());
+    }
+}
+
+pub fn set_debug(enabled: bool) {
+    match enabled {
+        true => log::set_max_level(LevelFilter::Debug),
+        false => log::set_max_level(LevelFilter::Off),
+    }
+}
+
+// Diagnostics that are not log records, like the search statistics, are only
+// sent while debug mode is on as well
+pub fn debug_enabled() -> bool {
+    log::max_level() >= LevelFilter::Debug
+}
+
+fn printing_loop<W: Write>(receiver: Receiver<OutputEvent>, mut output: W) {
+    // recv() blocks until the next event arrives and fails once every sender is
+    // dropped, so the loop neither busy waits nor outlives the engine
+    while let Ok(event) = receiver.recv() {
+        let written = match event {
+            OutputEvent::Info((score, best_move, depth, _)) => writeln!(
+                output,
+                "info depth {depth} score {} pv {best_move}",
+                format_score(score)
+            ),
+            OutputEvent::BestMove((score, best_move)) => {
+                writeln!(output, "info score {}", format_score(score))
+                    .and_then(|_| writeln!(output, "bestmove {}", format_move(best_move)))
+            }
+            OutputEvent::Raw(text) => writeln!(output, "{text}"),
+            OutputEvent::Shutdown => return,
+        };
+
+        // The GUI waits for complete lines, so every event is flushed right away
+        if written.and_then(|_| output.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+// Without a legal move the GUI expects the null move "0000"
+pub fn format_move(chess_move: ChessMove) -> String {
+    match chess_move == null_move() {
+        true => "0000".to_string(),
+        false => chess_move.to_string(),
+    }
+}
+
+pub fn format_score(score: PositionScore) -> String {
+    match mate_distance(score) {
+        Some(distance) => format!("mate {distance}"),
+        None => format!("cp {score}"),
+    }
+}
+
+// The distance to mate of a mate score, negative if the side to move is mated
+pub fn mate_distance(score: PositionScore) -> Option<PositionScore> {
+    if score.abs() <= config::MATE_THRESHOLD {
+        return None;
+    }
+
+    let mate_distance = config::INFINITY - score.abs();
+    match score < 0 {
+        true => Some(-mate_distance),
+        false => Some(mate_distance + 1),
+    }
+}
@@ -0,0 +1,32 @@
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+
+use crate::uci::uci_loop;
+use crate::xboard::xboard_loop;
+
+// The engine speaks both uci and xboard, the first command decides which one.
+// Anything but "xboard" starts the uci loop, which then also handles the
+// command itself.
+pub fn engine_mode() {
+    protocol_loop(BufReader::new(io::stdin()), io::stdout());
+}
+
+pub fn protocol_loop<R, W>(mut input: R, output: W)
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let mut first_line = String::new();
+    while first_line.trim().is_empty() {
+        first_line.clear();
+        match input.read_line(&mut first_line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+
+    let replayed = Cursor::new(first_line.clone().into_bytes()).chain(input);
+    match first_line.trim() {
+        "xboard" => xboard_loop(replayed, output),
+        _ => uci_loop(replayed, output),
+    }
+}
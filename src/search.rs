@@ -1,26 +1,52 @@
-use chess::{BitBoard, Board, BoardStatus, ChessMove, Color, MoveGen, Piece, Square, EMPTY};
+use chess::{BitBoard, Board, BoardStatus, ChessMove, MoveGen, Piece, Square, EMPTY};
 use derive_new::new;
 use std::cmp::max;
-use std::ops::Index;
-use std::rc::Rc;
-use std::sync::mpsc;
-
-use crate::config::{self, MAX_DEPTH, MAX_EXTENSION_PLIES};
-use crate::eval::evaluate;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+
+use crate::chess960::{castling_moves, CastlingRights};
+use crate::config::{self, MAX_EXTENSION_PLIES};
+use crate::eval::Evaluator;
+#[cfg(feature = "stats")]
+use crate::movelist::move_stage;
 use crate::movelist::MoveList;
+use crate::printer::OutputEvent;
+#[cfg(feature = "stats")]
+use crate::stats::SearchStats;
 use crate::table::{ScoreBound, TableEntryData, TranspositionTable};
+#[cfg(feature = "tree")]
+use crate::tree::{NodeReason, SearchTree};
 
 pub type PositionScore = i32;
 pub type SearchDepth = u8;
 pub type SearchOutcome = (PositionScore, ChessMove);
-pub type SearchInfo = (PositionScore, ChessMove, SearchDepth);
+// Sent after every completed iteration, the nodes are those of the sending thread
+pub type SearchInfo = (PositionScore, ChessMove, SearchDepth, u64);
+
+// The counters only exist with the "stats" feature, otherwise the field of the
+// SearchContext takes no space
+#[cfg(feature = "stats")]
+pub type Stats = SearchStats;
+#[cfg(not(feature = "stats"))]
+pub type Stats = ();
+
+// Same for the recorded search tree, which is only recorded while it is set
+#[cfg(feature = "tree")]
+pub type Tree = Option<SearchTree>;
+#[cfg(not(feature = "tree"))]
+pub type Tree = ();
+
+// Returned as best move when there is no legal move, printed as "0000"
+#[inline]
+pub fn null_move() -> ChessMove {
+    ChessMove::new(Square::A1, Square::A1, None)
+}
 
 // TODO: Add History Heuristic
 
 // TODO: instead of alpha, beta etc. pass an object that encapsulates a search state
 
-const MAX_HISTORY_VALUE: u16 = 65535 - (MAX_DEPTH as u16 * MAX_DEPTH as u16 + 1);
-
 #[derive(Clone, Copy)]
 pub struct KillerMoves {
     pub one: ChessMove,
@@ -39,78 +65,18 @@ impl KillerMoves {
 
 pub type Table64by64 = [[u16; 64]; 64];
 
-struct HistoryTables {
-    white: Table64by64,
-    black: Table64by64,
-}
-
-impl Index<Color> for HistoryTables {
-    type Output = Table64by64;
-    fn index(&self, color: Color) -> &Self::Output {
-        match color {
-            Color::White => &self.white,
-            Color::Black => &self.black,
-        }
-    }
-}
-
-impl HistoryTables {
-    fn new() -> HistoryTables {
-        HistoryTables {
-            white: [[0; 64]; 64],
-            black: [[0; 64]; 64],
-        }
-    }
-
-    fn increment(&mut self, chess_move: &ChessMove, color: Color, depth: SearchDepth) {
-        match color {
-            Color::White => {
-                self.white[chess_move.get_source().to_index()][chess_move.get_dest().to_index()] +=
-                    1;
-                // if self.white[chess_move.get_source().to_index()][chess_move.get_dest().to_index()]
-                //     >= MAX_HISTORY_VALUE
-                // {
-                //     self.divide_history_white();
-                // }
-            }
-            Color::Black => {
-                self.black[chess_move.get_source().to_index()][chess_move.get_dest().to_index()] +=
-                    1;
-                // if self.black[chess_move.get_source().to_index()][chess_move.get_dest().to_index()]
-                //     >= MAX_HISTORY_VALUE
-                // {
-                //     self.divide_history_black();
-                // }
-            }
-        }
-    }
-
-    fn divide_history_white(&mut self) {
-        for from_square in 0..64 {
-            for to_square in 0..64 {
-                self.white[from_square][to_square] >>= 1;
-            }
-        }
-    }
-
-    fn divide_history_black(&mut self) {
-        for from_square in 0..64 {
-            for to_square in 0..64 {
-                self.black[from_square][to_square] >>= 1;
-            }
-        }
-    }
-}
-
 #[derive(new)]
 pub struct SearchContext {
     // This struct contains all the information a thread needs search a position
     // In order to correctly identify a draw by threefold repetition, the SearchContext
     // needs to know the hashes of all previous positions. Also, after initializing, the
     // repetition_table needs to be incremented at the entries corresponding to these positions
+    //
+    // All threads of a search share the stop flag. The limits are only checked
+    // every STOP_CHECK_NODES nodes and reaching one sets the flag for everyone.
     pub board: Board,
-    pub receiver_channel: mpsc::Receiver<bool>,
-    pub sender_channel: mpsc::Sender<SearchInfo>,
+    pub stop_flag: Arc<AtomicBool>,
+    pub sender_channel: mpsc::Sender<OutputEvent>,
     pub hash_table: TranspositionTable,
 
     #[new(value = "[0; config::REP_TABLE_SIZE]")]
@@ -121,62 +87,116 @@ pub struct SearchContext {
     pub capture_order: [Piece; 5],
     #[new(value = "1")]
     pub start_depth: u8,
+    // Lazy SMP: helper threads (index > 0) skip some iterations, so that the
+    // threads of a group spread over several depths
+    #[new(value = "0")]
+    pub thread_index: usize,
+    #[new(value = "0")]
+    pub completed_depth: SearchDepth,
     #[new(value = "false")]
     terminate_search: bool,
+    #[new(value = "0")]
+    pub nodes: u64,
+    #[new(value = "None")]
+    pub node_limit: Option<u64>,
+    #[new(value = "None")]
+    pub deadline: Option<Instant>,
+    // Move ordering state, the killers and countermoves are kept across
+    // searches when the context is reused by a worker of the thread pool
     #[new(value = "vec![]")]
     killers: Vec<KillerMoves>,
-    #[new(value = "[[0; 64]; 64]")] //"HistoryTables::new()")]
-    history_tables: Table64by64, // HistoryTables,
+    // Not learned, only the random order of the quiet moves of this thread
+    #[new(value = "random_history()")]
+    history_tables: Table64by64,
+    #[new(value = "Box::new([[None; 64]; 64])")]
+    countermoves: Box<[[Option<ChessMove>; 64]; 64]>,
+    #[new(value = "vec![]")]
+    previous_moves: Vec<ChessMove>,
+    // Chess960 castling rights of the searched node. The move generator of the
+    // chess crate can not produce these castling moves and the board hash does
+    // not include the rights, both are added by the search. Empty in standard
+    // chess, where the board keeps the rights.
+    #[new(value = "CastlingRights::default()")]
+    pub castling: CastlingRights,
+    // "go searchmoves": only these root moves are searched, all if it is empty
+    #[new(value = "vec![]")]
+    pub root_moves: Vec<ChessMove>,
+    // Evaluation caches of this thread
+    #[new(value = "Evaluator::new()")]
+    pub evaluator: Evaluator,
+    #[new(value = "Default::default()")]
+    pub stats: Stats,
+    #[new(value = "Default::default()")]
+    pub tree: Tree,
 }
 
 impl SearchContext {
     pub fn root_search(&mut self, max_depth: SearchDepth) -> SearchOutcome {
         // TODO: order moves here with scores attached! (Hash moves gets good score, increase of alpha gets good score etc.)
-        let mut move_vec = get_legal_moves_vector(&self.board);
-        let mut best_move = move_vec[0];
-        let mut score = -config::INFINITY;
+        let mut move_vec: Vec<(ChessMove, Board)> = get_legal_moves_vector(&self.board)
+            .into_iter()
+            .map(|chess_move| (chess_move, self.board.make_move_new(chess_move)))
+            .chain(castling_moves(&self.board, &self.castling))
+            .filter(|(chess_move, _)| {
+                self.root_moves.is_empty() || self.root_moves.contains(chess_move)
+            })
+            .collect();
+
+        if move_vec.is_empty() {
+            return terminal_outcome(&self.board);
+        }
 
-        use rand::Rng;
-        let mut rng = rand::thread_rng();
+        let mut best_move = move_vec[0].0;
+        let mut score = -config::INFINITY;
 
-        for i in 0..64 {
-            for j in 0..64 {
-                self.history_tables[i][j] = rng.gen::<u16>()
-            }
+        let dummy_move = ChessMove::new(Square::A1, Square::A1, None);
+        let max_ply = (max_depth + MAX_EXTENSION_PLIES) as usize;
+
+        // Killers of an earlier search are kept, they are often still useful
+        if self.killers.len() < max_ply {
+            self.killers.resize(
+                max_ply,
+                KillerMoves {
+                    one: dummy_move,
+                    two: dummy_move,
+                },
+            );
         }
+        self.previous_moves.resize(max_ply + 1, dummy_move);
 
-        let dummy_move = ChessMove::new(Square::A1, Square::A1, None);
+        let root_hash = self.hash(&self.board);
+        let root_castling = self.castling;
+        self.set_visited(root_hash);
 
-        self.killers = vec![
-            KillerMoves {
-                one: dummy_move.clone(),
-                two: dummy_move.clone()
-            };
-            (max_depth + MAX_EXTENSION_PLIES) as usize
-        ];
+        'iterative_deepening: for depth in self.start_depth..(max_depth + 1) {
+            if skips_depth(self.thread_index, depth) {
+                continue;
+            }
 
-        self.set_visited(self.board.get_hash());
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.start_iteration(depth));
 
-        'iterative_deepening: for depth in self.start_depth..(max_depth + 1) {
             let mut current_best = best_move;
 
             // TODO: Add aspiration windows
 
             let mut alpha = -config::INFINITY;
 
-            move_vec.sort_by_key(|m| if best_move.eq(m) { 0 } else { 1 });
+            move_vec.sort_by_key(|(m, _)| if best_move.eq(m) { 0 } else { 1 });
 
-            for chess_move in &mut move_vec {
+            for (chess_move, child_board) in &move_vec {
                 // TODO: only search first move with full window, later moves with zero window
 
-                let value = -self.search(
-                    &self.board.make_move_new(*chess_move),
-                    depth - 1,
-                    -config::INFINITY,
-                    -alpha,
-                    0,
-                    0,
-                );
+                self.previous_moves[0] = *chess_move;
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.enter(*chess_move, -config::INFINITY, -alpha, depth - 1));
+                self.evaluator.push(&self.board, *chess_move, child_board);
+                self.castling = castling_after(&self.board, &root_castling, *chess_move);
+                let value = -self.search(child_board, depth - 1, -config::INFINITY, -alpha, 0, 0);
+                self.castling = root_castling;
+                self.evaluator.pop();
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.exit(-value));
 
                 if value > alpha {
                     current_best = *chess_move;
@@ -184,21 +204,33 @@ impl SearchContext {
                 }
 
                 if self.terminate_search {
+                    #[cfg(feature = "tree")]
+                    self.trace(|tree| tree.set_reason(NodeReason::Stopped));
                     break 'iterative_deepening;
                 }
             }
 
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.finish_root(alpha));
+
             score = alpha;
             best_move = current_best;
+            self.completed_depth = depth;
 
             // TODO: report also the PV once implemented (Read from TTable)
 
             self.sender_channel
-                .send((score, best_move, depth))
+                .send(OutputEvent::Info((score, best_move, depth, self.nodes)))
                 .unwrap_or_default();
         }
 
-        self.unset_visited(self.board.get_hash());
+        self.unset_visited(root_hash);
+
+        // Stopped before the first iteration ended, there is no score and
+        // -INFINITY would be reported as being mated
+        if self.completed_depth == 0 {
+            score = 0;
+        }
 
         (score, best_move)
     }
@@ -212,31 +244,55 @@ impl SearchContext {
         mut plies_extended: SearchDepth,
         ply: usize,
     ) -> PositionScore {
-        if self.terminate_search {
+        if self.should_stop() {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_reason(NodeReason::Stopped));
             return alpha;
         }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.nodes += 1;
+        }
 
-        if self.already_visited(board.get_hash()) {
+        let hash = self.hash(board);
+        if self.already_visited(hash) {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_reason(NodeReason::Repetition));
             return config::DRAW;
         }
 
-        if depth <= 0 || board.status() != BoardStatus::Ongoing {
+        // SearchDepth is unsigned, so this is the old `depth <= 0`
+        if depth == 0 || self.status(board) != BoardStatus::Ongoing {
             return self.quiescence_search(board, alpha, beta);
         }
 
         if extend_check(board, plies_extended) {
             depth += 1;
             plies_extended += 1;
+            #[cfg(feature = "stats")]
+            {
+                self.stats.extensions += 1;
+            }
         }
 
         // TODO: try if just ChessMove performs better than option type
         let mut hash_move: Option<ChessMove> = None;
-        let table_probe = self.hash_table.get(board.get_hash());
+        let table_probe = self.hash_table.get(hash);
+        #[cfg(feature = "stats")]
+        self.record_probe(table_probe.as_ref().map(|entry| entry.score_bound));
 
         if let Some(table_entry) = table_probe {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_table_hit());
             if table_entry.depth >= depth {
                 match table_entry.score_bound {
                     ScoreBound::Exact => {
+                        #[cfg(feature = "stats")]
+                        {
+                            self.stats.tt_cutoffs += 1;
+                        }
+                        #[cfg(feature = "tree")]
+                        self.trace(|tree| tree.set_reason(NodeReason::TableCutoff));
                         if table_entry.score > alpha {
                             alpha = table_entry.score;
                             if alpha >= beta {
@@ -259,30 +315,72 @@ impl SearchContext {
             }
 
             if alpha >= beta {
+                #[cfg(feature = "stats")]
+                {
+                    self.stats.tt_cutoffs += 1;
+                }
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.set_reason(NodeReason::TableCutoff));
                 return beta;
             }
 
             hash_move = Some(table_entry.best_move);
         }
 
-        let movelist = MoveList::new(board, hash_move, self.killers[ply], self.history_tables);
+        let previous_move = self.previous_moves[ply];
+        let countermove = self.countermoves[previous_move.get_source().to_index()]
+            [previous_move.get_dest().to_index()];
+        let movelist = MoveList::new(
+            board,
+            hash_move,
+            self.killers[ply],
+            countermove,
+            self.history_tables,
+        );
 
         let mut score_bound = ScoreBound::UpperBound;
         let mut best_move = ChessMove::new(Square::A1, Square::A1, None);
 
-        self.set_visited(board.get_hash());
+        self.set_visited(hash);
 
-        for chess_move in movelist {
+        #[cfg(feature = "stats")]
+        let mut searched_moves = 0;
+        #[cfg(feature = "stats")]
+        let killers = self.killers[ply];
+
+        // Chess960 castling moves come last, after the quiet moves
+        let castling = self.castling;
+        let moves = movelist
+            .map(|chess_move| (chess_move, board.make_move_new(chess_move)))
+            .chain(castling_moves(board, &castling));
+
+        // The counter only exists with the stats feature, so enumerate() is not used
+        #[allow(clippy::explicit_counter_loop)]
+        for (chess_move, child_board) in moves {
             // TODO: only search first move with full window, later moves with zero window
 
+            self.previous_moves[ply + 1] = chess_move;
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.enter(chess_move, -beta, -alpha, depth - 1));
+            self.evaluator.push(board, chess_move, &child_board);
+            self.castling = castling_after(board, &castling, chess_move);
             let mut value = -self.search(
-                &board.make_move_new(chess_move),
+                &child_board,
                 depth - 1,
                 -beta,
                 -alpha,
                 plies_extended,
                 ply + 1,
             );
+            self.castling = castling;
+            self.evaluator.pop();
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.exit(-value));
+
+            // The value of an interrupted subtree is meaningless
+            if self.terminate_search {
+                break;
+            }
 
             if value > config::MATE_THRESHOLD {
                 value -= 1;
@@ -295,6 +393,13 @@ impl SearchContext {
 
                 if alpha >= beta {
                     // TODO if movegen.stage == quiet
+                    #[cfg(feature = "tree")]
+                    self.trace(|tree| tree.set_reason(NodeReason::BetaCutoff));
+                    #[cfg(feature = "stats")]
+                    self.stats.record_cutoff(
+                        move_stage(board, chess_move, hash_move, killers, countermove),
+                        searched_moves,
+                    );
 
                     match board.piece_on(chess_move.get_dest()) {
                         Some(_) => {}
@@ -306,35 +411,39 @@ impl SearchContext {
                             // self.history_tables[chess_move.get_source().to_index()]
                             //     [chess_move.get_dest().to_index()] += depth as u16;
                             self.killers[ply].store(chess_move);
+                            self.countermoves[previous_move.get_source().to_index()]
+                                [previous_move.get_dest().to_index()] = Some(chess_move);
                         }
                     }
                     score_bound = ScoreBound::LowerBound;
                     break;
                 }
             }
-        }
-
-        self.unset_visited(board.get_hash());
 
-        if self.receiver_channel.try_recv().unwrap_or(false) {
-            self.terminate_search = true;
+            #[cfg(feature = "stats")]
+            {
+                searched_moves += 1;
+            }
         }
 
+        self.unset_visited(hash);
+
         if self.terminate_search {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_reason(NodeReason::Stopped));
             return alpha;
         }
 
         let table_entry = TableEntryData {
-            best_move: best_move,
+            best_move,
             score: alpha,
-            depth: depth,
-            score_bound: score_bound,
+            depth,
+            score_bound,
         };
 
-        self.hash_table
-            .replace_if(board.get_hash(), table_entry, |old_entry| {
-                old_entry.depth <= depth || table_entry.score_bound == ScoreBound::Exact
-            });
+        self.hash_table.replace_if(hash, table_entry, |old_entry| {
+            old_entry.depth <= depth || table_entry.score_bound == ScoreBound::Exact
+        });
 
         match score_bound {
             ScoreBound::LowerBound => beta,
@@ -348,22 +457,54 @@ impl SearchContext {
         mut alpha: PositionScore,
         mut beta: PositionScore,
     ) -> i32 {
-        match board.status() {
-            BoardStatus::Checkmate => return -config::INFINITY,
-            BoardStatus::Stalemate => return config::DRAW,
+        if self.should_stop() {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_reason(NodeReason::Stopped));
+            return alpha;
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.qnodes += 1;
+        }
+        #[cfg(feature = "tree")]
+        self.trace(|tree| tree.set_quiescence());
+
+        match self.status(board) {
+            BoardStatus::Checkmate => {
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.set_reason(NodeReason::Checkmate));
+                return -config::INFINITY;
+            }
+            BoardStatus::Stalemate => {
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.set_reason(NodeReason::Stalemate));
+                return config::DRAW;
+            }
             BoardStatus::Ongoing => {}
         }
 
-        alpha = max(evaluate(board), alpha);
+        alpha = max(self.evaluator.evaluate(board), alpha);
 
         if alpha >= beta {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_reason(NodeReason::StandPat));
             return beta;
         };
 
-        let table_probe = self.hash_table.get(board.get_hash());
+        let table_probe = self.hash_table.get(self.hash(board));
+        #[cfg(feature = "stats")]
+        self.record_probe(table_probe.as_ref().map(|entry| entry.score_bound));
         if let Some(table_entry) = table_probe {
+            #[cfg(feature = "tree")]
+            self.trace(|tree| tree.set_table_hit());
             match table_entry.score_bound {
                 ScoreBound::Exact => {
+                    #[cfg(feature = "stats")]
+                    {
+                        self.stats.tt_cutoffs += 1;
+                    }
+                    #[cfg(feature = "tree")]
+                    self.trace(|tree| tree.set_reason(NodeReason::TableCutoff));
                     alpha = table_entry.score;
                     if alpha >= beta {
                         return beta;
@@ -382,21 +523,43 @@ impl SearchContext {
                 }
             }
             if alpha >= beta {
+                #[cfg(feature = "stats")]
+                {
+                    self.stats.tt_cutoffs += 1;
+                }
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.set_reason(NodeReason::TableCutoff));
                 return beta;
             }
         }
 
+        let castling = self.castling;
         let mut iterable = MoveGen::new_legal(board);
         for piece in config::QS_ORDERING {
             iterable.set_iterator_mask(get_targets(board, piece));
 
             for chess_move in &mut iterable {
-                alpha = max(
-                    alpha,
-                    -self.quiescence_search(&board.make_move_new(chess_move), -beta, -alpha),
-                );
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.enter(chess_move, -beta, -alpha, 0));
+                let child_board = board.make_move_new(chess_move);
+                self.evaluator.push(board, chess_move, &child_board);
+                self.castling = castling_after(board, &castling, chess_move);
+                let value = -self.quiescence_search(&child_board, -beta, -alpha);
+                self.castling = castling;
+                self.evaluator.pop();
+                #[cfg(feature = "tree")]
+                self.trace(|tree| tree.exit(-value));
+                alpha = max(alpha, value);
+
+                if self.terminate_search {
+                    #[cfg(feature = "tree")]
+                    self.trace(|tree| tree.set_reason(NodeReason::Stopped));
+                    return alpha;
+                }
 
                 if alpha >= beta {
+                    #[cfg(feature = "tree")]
+                    self.trace(|tree| tree.set_reason(NodeReason::BetaCutoff));
                     return beta;
                 }
             }
@@ -404,6 +567,85 @@ impl SearchContext {
         alpha
     }
 
+    // Prepares the context for a new search of the given board, the move
+    // ordering state of the last search is kept
+    pub fn reset_search(&mut self, board: Board) {
+        for hash in self.past_position_hashes.drain(..) {
+            self.repetition_table[hash as usize % config::REP_TABLE_SIZE] = 0;
+        }
+        self.board = board;
+        self.terminate_search = false;
+        self.nodes = 0;
+        self.node_limit = None;
+        self.deadline = None;
+        self.completed_depth = 0;
+        self.castling = CastlingRights::default();
+        self.root_moves.clear();
+        self.stats = Default::default();
+    }
+
+    // Forgets everything learned in earlier games
+    pub fn clear_move_ordering(&mut self) {
+        self.killers.clear();
+        self.history_tables = random_history();
+        *self.countermoves = [[None; 64]; 64];
+    }
+
+    // The key of the node for repetitions and the transposition table
+    #[inline]
+    fn hash(&self, board: &Board) -> u64 {
+        board.get_hash() ^ self.castling.hash()
+    }
+
+    // The chess crate does not know Chess960 castling, a position in which
+    // castling is the only legal move is not a stalemate
+    fn status(&self, board: &Board) -> BoardStatus {
+        match board.status() {
+            BoardStatus::Stalemate if !castling_moves(board, &self.castling).is_empty() => {
+                BoardStatus::Ongoing
+            }
+            status => status,
+        }
+    }
+
+    // Counts the node and tells whether the search has to be aborted
+    #[inline]
+    fn should_stop(&mut self) -> bool {
+        self.nodes += 1;
+
+        if self.nodes.is_multiple_of(config::STOP_CHECK_NODES) && self.limit_reached() {
+            self.stop_flag.store(true, Ordering::Relaxed);
+        }
+        if self.stop_flag.load(Ordering::Relaxed) {
+            self.terminate_search = true;
+        }
+        self.terminate_search
+    }
+
+    #[cfg(feature = "stats")]
+    #[inline]
+    fn record_probe(&mut self, score_bound: Option<ScoreBound>) {
+        self.stats.tt_probes += 1;
+        if let Some(score_bound) = score_bound {
+            self.stats.record_tt_hit(score_bound);
+        }
+    }
+
+    #[cfg(feature = "tree")]
+    #[inline]
+    fn trace(&mut self, record: impl FnOnce(&mut SearchTree)) {
+        if let Some(tree) = self.tree.as_mut() {
+            record(tree);
+        }
+    }
+
+    fn limit_reached(&self) -> bool {
+        self.node_limit.is_some_and(|limit| self.nodes >= limit)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
     #[inline]
     pub fn already_visited(&mut self, position_hash: u64) -> bool {
         if self.repetition_table[position_hash as usize % config::REP_TABLE_SIZE] >= 1 {
@@ -430,11 +672,63 @@ impl SearchContext {
     }
 }
 
+// The castling rights after a move, only Chess960 rights change here
+#[inline]
+fn castling_after(
+    board: &Board,
+    castling: &CastlingRights,
+    chess_move: ChessMove,
+) -> CastlingRights {
+    let mut next_castling = *castling;
+    if !castling.is_empty() {
+        next_castling.update(board, chess_move);
+    }
+    next_castling
+}
+
 #[inline]
 pub fn extend_check(board: &chess::Board, plies_extended: SearchDepth) -> bool {
     (*board.checkers() != EMPTY) && (plies_extended < config::MAX_EXTENSION_PLIES)
 }
 
+// The outcome of a position without legal moves, mated or stalemate
+pub fn terminal_outcome(board: &Board) -> SearchOutcome {
+    match *board.checkers() != EMPTY {
+        true => (-config::INFINITY, null_move()),
+        false => (config::DRAW, null_move()),
+    }
+}
+
+// The quiet moves are ordered by the history table, which is random and never
+// updated, so that the threads of a search order them differently
+fn random_history() -> Table64by64 {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+
+    let mut history_tables = [[0; 64]; 64];
+    for row in history_tables.iter_mut() {
+        for value in row.iter_mut() {
+            *value = rng.gen::<u16>();
+        }
+    }
+    history_tables
+}
+
+// Depth skipping schedule of the helper threads, thread i skips the depths
+// where ((depth + SKIP_PHASE[i]) / SKIP_SIZE[i]) is odd. The schedules repeat
+// after 20 threads.
+const SKIP_SIZE: [SearchDepth; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [SearchDepth; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+pub fn skips_depth(thread_index: usize, depth: SearchDepth) -> bool {
+    if thread_index == 0 {
+        return false;
+    }
+
+    let schedule = (thread_index - 1) % SKIP_SIZE.len();
+    (depth + SKIP_PHASE[schedule]) / SKIP_SIZE[schedule] % 2 == 1
+}
+
 #[inline]
 fn get_legal_moves_vector(board: &Board) -> Vec<ChessMove> {
     let mut iterable = MoveGen::new_legal(board);
@@ -0,0 +1,100 @@
+use std::fmt;
+
+use crate::movelist::MoveOrderingStage;
+use crate::table::ScoreBound;
+
+// Counters collected by a SearchContext when the "stats" feature is enabled.
+// Without the feature neither this module nor the counting code is compiled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub tt_probes: u64,
+    // Indexed by bound: exact, lower bound, upper bound
+    pub tt_hits: [u64; 3],
+    pub tt_cutoffs: u64,
+    pub beta_cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    pub stage_cutoffs: [u64; MoveOrderingStage::COUNT],
+    pub extensions: u64,
+}
+
+impl SearchStats {
+    #[inline]
+    pub fn record_tt_hit(&mut self, score_bound: ScoreBound) {
+        self.tt_hits[bound_index(score_bound)] += 1;
+    }
+
+    #[inline]
+    pub fn record_cutoff(&mut self, stage: MoveOrderingStage, move_number: usize) {
+        self.beta_cutoffs += 1;
+        self.stage_cutoffs[stage as usize] += 1;
+        if move_number == 0 {
+            self.first_move_cutoffs += 1;
+        }
+    }
+
+    pub fn tt_hit_rate(&self, score_bound: ScoreBound) -> f64 {
+        ratio(self.tt_hits[bound_index(score_bound)], self.tt_probes)
+    }
+
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        ratio(self.first_move_cutoffs, self.beta_cutoffs)
+    }
+
+    pub fn merge(&mut self, other: &SearchStats) {
+        self.nodes += other.nodes;
+        self.qnodes += other.qnodes;
+        self.tt_probes += other.tt_probes;
+        self.tt_cutoffs += other.tt_cutoffs;
+        self.beta_cutoffs += other.beta_cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.extensions += other.extensions;
+        for (total, count) in self.tt_hits.iter_mut().zip(other.tt_hits) {
+            *total += count;
+        }
+        for (total, count) in self.stage_cutoffs.iter_mut().zip(other.stage_cutoffs) {
+            *total += count;
+        }
+    }
+}
+
+// One line, so it can be sent as "info string"
+impl fmt::Display for SearchStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nodes {} qnodes {} tt probes {} exact {:.1}% lower {:.1}% upper {:.1}% tt cutoffs {} \
+             cutoffs {} first move {:.1}%",
+            self.nodes,
+            self.qnodes,
+            self.tt_probes,
+            self.tt_hit_rate(ScoreBound::Exact) * 100.0,
+            self.tt_hit_rate(ScoreBound::LowerBound) * 100.0,
+            self.tt_hit_rate(ScoreBound::UpperBound) * 100.0,
+            self.tt_cutoffs,
+            self.beta_cutoffs,
+            self.first_move_cutoff_rate() * 100.0,
+        )?;
+        for stage in MoveOrderingStage::ALL {
+            write!(f, " {stage:?} {}", self.stage_cutoffs[stage as usize])?;
+        }
+        write!(f, " extensions {}", self.extensions)
+    }
+}
+
+#[inline]
+fn bound_index(score_bound: ScoreBound) -> usize {
+    match score_bound {
+        ScoreBound::Exact => 0,
+        ScoreBound::LowerBound => 1,
+        ScoreBound::UpperBound => 2,
+    }
+}
+
+fn ratio(count: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => count as f64 / total as f64,
+    }
+}
@@ -9,7 +9,7 @@ pub type TranspositionTable = SharedTable<TableEntryData>;
 
 // TODO: Add the flag PV
 
-#[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
 pub enum ScoreBound {
     Exact,
     UpperBound,
@@ -53,6 +53,10 @@ impl<T: Copy + Clone + PartialEq + PartialOrd> SharedTable<T> {
         }
     }
 
+    pub fn entry_size() -> usize {
+        std::mem::size_of::<RwLock<TableEntry<T>>>()
+    }
+
     #[inline]
     pub fn get(&self, hash: u64) -> Option<T> {
         let idx = (hash as usize) & self.mask;
@@ -1,11 +1,17 @@
 use crate::config;
+use crate::protocol::protocol_loop;
 use crate::search::SearchContext;
 use crate::table::{ScoreBound, TableEntryData, TranspositionTable};
 use chess::{Board, ChessMove, Square};
-use std::sync::mpsc::channel;
+use std::io::{self, BufReader, PipeWriter, Write};
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 pub fn setup_test_context(board: Board) -> SearchContext {
-    let (_, rx) = channel();
     let (tx, _) = channel();
     let hash_table = TranspositionTable::new(
         config::HASH_TABLE_SIZE as usize,
@@ -17,5 +23,164 @@ pub fn setup_test_context(board: Board) -> SearchContext {
         },
     );
 
-    SearchContext::new(board, rx, tx, hash_table)
+    SearchContext::new(board, Arc::new(AtomicBool::new(false)), tx, hash_table)
+}
+
+// The same position with the colors swapped and the board mirrored vertically,
+// a symmetric evaluation gives it the same score for the side to move
+pub fn flip_colors(board: &Board) -> Board {
+    let fen = board.to_string();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+
+    let placement: Vec<String> = fields[0].rsplit('/').map(swap_case).collect();
+    let side_to_move = match fields[1] {
+        "w" => "b",
+        _ => "w",
+    };
+    let castling = match fields[2] {
+        "-" => "-".to_string(),
+        rights => {
+            let swapped = swap_case(rights);
+            let (white, black): (String, String) = swapped
+                .chars()
+                .partition(|right| right.is_ascii_uppercase());
+            white + &black
+        }
+    };
+    let en_passant = match fields[3] {
+        "-" => "-".to_string(),
+        square => square.replace('3', "x").replace('6', "3").replace('x', "6"),
+    };
+
+    let flipped = format!(
+        "{} {side_to_move} {castling} {en_passant} {}",
+        placement.join("/"),
+        fields[4..].join(" ")
+    );
+    Board::from_str(&flipped).expect("Flipped position is valid")
+}
+
+// The same position mirrored left to right. Castling rights can not be
+// mirrored and are dropped, the evaluation does not look at them.
+pub fn mirror_files(board: &Board) -> Board {
+    let fen = board.to_string();
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+
+    let placement: Vec<String> = fields[0]
+        .split('/')
+        .map(|rank| rank.chars().rev().collect())
+        .collect();
+    let en_passant = match fields[3] {
+        "-" => "-".to_string(),
+        square => {
+            let file = square.as_bytes()[0];
+            format!("{}{}", (b'a' + b'h' - file) as char, &square[1..])
+        }
+    };
+
+    let mirrored = format!(
+        "{} {} - {en_passant} {}",
+        placement.join("/"),
+        fields[1],
+        fields[4..].join(" ")
+    );
+    Board::from_str(&mirrored).expect("Mirrored position is valid")
+}
+
+fn swap_case(text: &str) -> String {
+    text.chars()
+        .map(|character| match character.is_ascii_uppercase() {
+            true => character.to_ascii_lowercase(),
+            false => character.to_ascii_uppercase(),
+        })
+        .collect()
+}
+
+// Collects everything the engine writes and hands it out line by line
+struct LineWriter {
+    sender: Sender<String>,
+    buffer: Vec<u8>,
+}
+
+impl Write for LineWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        for byte in bytes {
+            if *byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.buffer).to_string();
+                self.buffer.clear();
+                self.sender
+                    .send(line)
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            } else {
+                self.buffer.push(*byte);
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs the engine on a separate thread, commands are written to a pipe and the
+// output is read back line by line, so tests can check the exact transcript.
+// As for the binary, the first command selects uci or xboard.
+pub struct TestEngine {
+    input: Option<PipeWriter>,
+    output: Receiver<String>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl TestEngine {
+    pub fn start() -> TestEngine {
+        let (reader, writer) = io::pipe().expect("Could not create pipe");
+        let (sender, receiver) = channel();
+        let output = LineWriter {
+            sender,
+            buffer: vec![],
+        };
+        let handle = thread::spawn(move || protocol_loop(BufReader::new(reader), output));
+
+        TestEngine {
+            input: Some(writer),
+            output: receiver,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn send(&mut self, command: &str) {
+        let input = self.input.as_mut().expect("Input already closed");
+        writeln!(input, "{command}").expect("Engine closed its input");
+    }
+
+    pub fn read_line(&self, timeout: Duration) -> Option<String> {
+        self.output.recv_timeout(timeout).ok()
+    }
+
+    pub fn read_until(&self, prefix: &str, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut lines = vec![];
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            match self.read_line(remaining) {
+                Some(line) => {
+                    let found = line.starts_with(prefix);
+                    lines.push(line);
+                    if found {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        lines
+    }
+
+    pub fn close(mut self) {
+        self.input = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
 }
@@ -1,19 +1,53 @@
 use chess::{ChessMove, Piece, Square};
-use std::sync::mpsc::{channel, Sender};
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::Instant;
 
-use crate::search::{SearchContext, SearchInfo, SearchOutcome};
+use crate::config;
+use crate::nnue::Network;
+#[cfg(feature = "tune")]
+use crate::params::EvalParams;
+use crate::position::Position;
+#[cfg(feature = "stats")]
+use crate::printer::debug_enabled;
+use crate::printer::OutputEvent;
+use crate::search::{PositionScore, SearchContext, SearchDepth, SearchOutcome, Stats};
+#[cfg(feature = "stats")]
+use crate::stats::SearchStats;
 use crate::table::{ScoreBound, TableEntryData, TranspositionTable};
-use crate::uci::Position;
+#[cfg(feature = "tree")]
+use crate::tree::{SearchTree, TreeFormat};
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum SearchGroupError {
     AlreadyRunning,
-    StartAgentError,
+    NotRunning,
+    ThreadPanicked,
+    InvalidParameters(String),
+    NoLegalMoves,
 }
 
-const STOP_SIGNAL: bool = true;
-const N_STOP_SIGNALS: u32 = 10;
+impl fmt::Display for SearchGroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SearchGroupError::AlreadyRunning => write!(f, "search already running"),
+            SearchGroupError::NotRunning => write!(f, "no search running"),
+            SearchGroupError::ThreadPanicked => write!(f, "search thread panicked"),
+            SearchGroupError::InvalidParameters(reason) => {
+                write!(f, "invalid search parameters: {reason}")
+            }
+            SearchGroupError::NoLegalMoves => write!(f, "no legal moves"),
+        }
+    }
+}
+
+// Keeps the weight of the worst thread above zero
+const VOTE_OFFSET: PositionScore = 14;
 
 const ORDERINGS: [[Piece; 5]; 7] = [
     [
@@ -67,132 +101,729 @@ const ORDERINGS: [[Piece; 5]; 7] = [
     ],
 ];
 
-pub struct SearchGroup {
-    principal: SearchAgent,
-    agents: Vec<SearchAgent>,
+// Searches run on a pool of worker threads that live as long as the engine.
+// Every worker keeps its SearchContext between searches, so neither threads
+// nor the move ordering state (killers, countermoves) have to be rebuilt for
+// every move. The transposition table is kept as well.
+//
+// The pool runs one search at a time: resize, set_table_size and new_game
+// may only be called while no SearchGroup of the pool is running.
+pub struct ThreadPool {
+    workers: Vec<Worker>,
+    table_size: u32,
+    hash_table: Option<TranspositionTable>,
+    network: Option<Arc<Network>>,
+    #[cfg(feature = "tune")]
+    eval_params: Arc<EvalParams>,
 }
 
-impl SearchGroup {
+struct Worker {
+    jobs: Sender<WorkerMessage>,
+    handle: JoinHandle<()>,
+}
+
+enum WorkerMessage {
+    Search(Box<SearchJob>),
+    NewGame,
+    Exit,
+}
+
+// The plies and the output format of a search whose tree is recorded, only
+// possible with the "tree" feature
+#[cfg(feature = "tree")]
+type TreeRequest = Option<(usize, TreeFormat)>;
+#[cfg(feature = "tree")]
+const NO_TREE: TreeRequest = None;
+#[cfg(not(feature = "tree"))]
+type TreeRequest = ();
+#[cfg(not(feature = "tree"))]
+const NO_TREE: TreeRequest = ();
+
+struct SearchJob {
+    position: Position,
+    info_sender: Sender<OutputEvent>,
+    hash_table: TranspositionTable,
+    stop_flag: Arc<AtomicBool>,
+    max_depth: SearchDepth,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    root_moves: Vec<ChessMove>,
+    #[cfg_attr(not(feature = "tree"), allow(dead_code))]
+    tree: TreeRequest,
+    result_sender: Sender<(AgentResult, Stats)>,
+    network: Option<Arc<Network>>,
+    #[cfg(feature = "tune")]
+    eval_params: Arc<EvalParams>,
+    // Only set for the principal, the search is finished when its job is
+    // dropped after the result was sent
+    _completion: Option<CompletionGuard>,
+}
+
+// Called once the result of a search can be collected, so a protocol loop
+// can block on its input and still learn about the end of a search
+pub type FinishedCallback = Arc<dyn Fn() + Send + Sync>;
+
+// Lets SearchEngine::wait block until the principal thread is done. Dropping
+// the job also happens when the search panics or the job is never received,
+// so a waiter can not miss the end of a search.
+#[derive(Default)]
+struct Completion {
+    state: Mutex<CompletionState>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct CompletionState {
+    done: bool,
+    callback: Option<FinishedCallback>,
+}
+
+impl Completion {
+    fn finish(&self) {
+        let callback = {
+            let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+            state.done = true;
+            state.callback.take()
+        };
+        self.finished.notify_all();
+        if let Some(callback) = callback {
+            callback();
+        }
+    }
+
+    fn wait(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        while !state.done {
+            state = self
+                .finished
+                .wait(state)
+                .unwrap_or_else(|error| error.into_inner());
+        }
+    }
+
+    // Called right away if the search is already done
+    fn call_when_finished(&self, callback: FinishedCallback) {
+        let mut state = self.state.lock().unwrap_or_else(|error| error.into_inner());
+        match state.done {
+            true => {
+                drop(state);
+                callback();
+            }
+            false => state.callback = Some(callback),
+        }
+    }
+}
+
+struct CompletionGuard(Arc<Completion>);
+
+impl Drop for CompletionGuard {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+impl ThreadPool {
+    pub fn new(num_threads: u8, table_size: u32) -> ThreadPool {
+        let mut pool = ThreadPool {
+            workers: vec![],
+            table_size,
+            hash_table: None,
+            network: None,
+            #[cfg(feature = "tune")]
+            eval_params: Arc::new(EvalParams::default()),
+        };
+        pool.resize(num_threads);
+        pool
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn resize(&mut self, num_threads: u8) {
+        let num_threads = num_threads.max(1) as usize;
+
+        while self.workers.len() > num_threads {
+            if let Some(worker) = self.workers.pop() {
+                worker.exit();
+            }
+        }
+        while self.workers.len() < num_threads {
+            self.workers.push(Worker::spawn(self.workers.len()));
+        }
+    }
+
+    // The table is allocated lazily by the next search
+    pub fn set_table_size(&mut self, table_size: u32) {
+        if table_size != self.table_size {
+            self.table_size = table_size;
+            self.hash_table = None;
+        }
+    }
+
+    // None switches the workers back to the classical evaluation
+    pub fn set_network(&mut self, network: Option<Arc<Network>>) {
+        self.network = network;
+    }
+
+    #[cfg(feature = "tune")]
+    pub fn set_eval_params(&mut self, eval_params: Arc<EvalParams>) {
+        self.eval_params = eval_params;
+    }
+
+    // Clears the transposition table and the move ordering state of all workers
+    pub fn new_game(&mut self) {
+        self.hash_table = None;
+        for worker in &self.workers {
+            let _ = worker.jobs.send(WorkerMessage::NewGame);
+        }
+    }
+
     pub fn start(
-        position: Position,
-        num_threads: u8,
-        info_sender: Sender<SearchInfo>,
-        table_size: u32,
-        max_depth: u8,
-        time_limit: Option<f32>,
-    ) -> SearchGroup {
-        assert!(num_threads > 0);
-
-        let hash_table = TranspositionTable::new(
-            table_size as usize,
-            TableEntryData {
-                best_move: ChessMove::new(Square::A1, Square::A1, None),
-                score: 0,
-                depth: 0,
-                score_bound: ScoreBound::LowerBound,
-            },
-        );
-
-        let (context, stop_sender) =
-            create_search_context(info_sender, &position, hash_table.clone());
-        let principal = SearchAgent::start(context, stop_sender, max_depth, time_limit);
+        &mut self,
+        position: &Position,
+        info_sender: Sender<OutputEvent>,
+        max_depth: SearchDepth,
+        deadline: Option<Instant>,
+        node_limit: Option<u64>,
+        root_moves: &[ChessMove],
+    ) -> Result<SearchGroup, SearchGroupError> {
+        self.start_jobs(
+            position,
+            info_sender,
+            max_depth,
+            deadline,
+            node_limit,
+            root_moves,
+            NO_TREE,
+        )
+    }
 
-        let (dummy_sender, _) = channel();
+    // Searches on the first worker only and records the tree of the last
+    // iteration, which the worker prints before it returns its result
+    #[cfg(feature = "tree")]
+    pub fn start_tree(
+        &mut self,
+        position: &Position,
+        info_sender: Sender<OutputEvent>,
+        depth: SearchDepth,
+        plies: usize,
+        format: TreeFormat,
+    ) -> Result<SearchGroup, SearchGroupError> {
+        self.start_jobs(
+            position,
+            info_sender,
+            depth,
+            None,
+            None,
+            &[],
+            Some((plies, format)),
+        )
+    }
 
-        let mut agents: Vec<SearchAgent> = vec![];
-        for n_thread in 0..num_threads - 1 {
-            let (mut agent_context, agent_stop_sender) =
-                create_search_context(dummy_sender.clone(), &position, hash_table.clone());
+    #[allow(clippy::too_many_arguments)]
+    fn start_jobs(
+        &mut self,
+        position: &Position,
+        info_sender: Sender<OutputEvent>,
+        max_depth: SearchDepth,
+        deadline: Option<Instant>,
+        node_limit: Option<u64>,
+        root_moves: &[ChessMove],
+        tree: TreeRequest,
+    ) -> Result<SearchGroup, SearchGroupError> {
+        if max_depth == 0 || max_depth > config::MAX_DEPTH {
+            return Err(SearchGroupError::InvalidParameters(format!(
+                "depth {max_depth} not in 1..={}",
+                config::MAX_DEPTH
+            )));
+        }
+        if position.legal_moves().is_empty() {
+            return Err(SearchGroupError::NoLegalMoves);
+        }
 
-            agent_context.capture_order = ORDERINGS[(n_thread % 7) as usize];
-            agent_context.start_depth = n_thread + 1;
+        // A worker whose search panicked is gone, it is replaced by a fresh one
+        for (index, worker) in self.workers.iter_mut().enumerate() {
+            if worker.handle.is_finished() {
+                *worker = Worker::spawn(index);
+            }
+        }
 
-            let agent = SearchAgent::start(agent_context, agent_stop_sender, max_depth, time_limit);
+        let table_size = self.table_size;
+        let hash_table = self
+            .hash_table
+            .get_or_insert_with(|| new_hash_table(table_size))
+            .clone();
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let completion = Arc::new(Completion::default());
+        // Only the principal reports its progress
+        let (dummy_sender, _) = channel();
 
-            agents.push(agent);
+        // A recorded tree comes from a single thread
+        #[cfg(feature = "tree")]
+        let workers = &self.workers[..tree.map_or(self.workers.len(), |_| 1)];
+        #[cfg(not(feature = "tree"))]
+        let workers = &self.workers[..];
+
+        let mut results = vec![];
+        for (index, worker) in workers.iter().enumerate() {
+            let (result_sender, result_receiver) = channel();
+            let principal = index == 0;
+
+            let job = SearchJob {
+                position: position.clone(),
+                info_sender: match principal {
+                    true => info_sender.clone(),
+                    false => dummy_sender.clone(),
+                },
+                hash_table: hash_table.clone(),
+                stop_flag: stop_flag.clone(),
+                max_depth,
+                deadline: deadline.filter(|_| principal),
+                node_limit: node_limit.filter(|_| principal),
+                root_moves: root_moves.to_vec(),
+                tree,
+                result_sender,
+                network: self.network.clone(),
+                #[cfg(feature = "tune")]
+                eval_params: self.eval_params.clone(),
+                _completion: principal.then(|| CompletionGuard(completion.clone())),
+            };
+            // A failed send drops the result sender, which counts as a failed agent
+            let _ = worker.jobs.send(WorkerMessage::Search(Box::new(job)));
+            results.push(result_receiver);
         }
 
-        SearchGroup { principal, agents }
+        let principal = results.remove(0);
+        Ok(SearchGroup {
+            principal,
+            principal_result: RefCell::new(None),
+            agents: results,
+            stop_flag,
+            completion,
+            #[cfg(feature = "stats")]
+            info_sender,
+        })
     }
 
-    pub fn stop(self) -> Result<SearchOutcome, ()> {
-        for agent in self.agents {
-            let _ = agent.stop(N_STOP_SIGNALS);
+    pub fn shutdown(mut self) {
+        for worker in self.workers.drain(..) {
+            worker.exit();
         }
+    }
+}
 
-        match self.principal.stop(N_STOP_SIGNALS) {
-            Ok(outcome) => Ok(outcome),
-            Err(_) => Err(()),
+impl Drop for ThreadPool {
+    // Workers finish their current search before they exit, so dropping the
+    // pool does not wait for them
+    fn drop(&mut self) {
+        for worker in &self.workers {
+            let _ = worker.jobs.send(WorkerMessage::Exit);
         }
     }
+}
 
-    pub fn await_principal(self) -> Result<SearchOutcome, ()> {
-        let search_outcome: Result<SearchOutcome, ()>;
+impl Worker {
+    fn spawn(thread_index: usize) -> Worker {
+        let (jobs, receiver) = channel();
 
-        match self.principal.stop(0) {
-            Ok(outcome) => search_outcome = Ok(outcome),
-            Err(_) => search_outcome = Err(()),
+        Worker {
+            jobs,
+            handle: thread::spawn(move || worker_loop(thread_index, receiver)),
         }
+    }
+
+    fn exit(self) {
+        let _ = self.jobs.send(WorkerMessage::Exit);
+        let _ = self.handle.join();
+    }
+}
 
-        for agent in self.agents {
-            let _ = agent.stop(N_STOP_SIGNALS);
+fn worker_loop(thread_index: usize, jobs: Receiver<WorkerMessage>) {
+    let mut context: Option<SearchContext> = None;
+
+    while let Ok(message) = jobs.recv() {
+        match message {
+            WorkerMessage::Search(job) => {
+                let context = match context.as_mut() {
+                    Some(context) => {
+                        prepare_search_context(context, &job);
+                        context
+                    }
+                    None => context.insert(create_search_context(thread_index, &job)),
+                };
+
+                #[cfg(feature = "tree")]
+                {
+                    context.tree = job.tree.map(|(plies, _)| SearchTree::new(plies));
+                }
+                let outcome = context.root_search(job.max_depth);
+                #[cfg(feature = "tree")]
+                if let (Some((_, format)), Some(tree)) = (job.tree, context.tree.take()) {
+                    for line in tree.export(format).lines() {
+                        let _ = job.info_sender.send(OutputEvent::Raw(line.to_string()));
+                    }
+                }
+                let result = AgentResult {
+                    outcome,
+                    depth: context.completed_depth,
+                };
+                let _ = job
+                    .result_sender
+                    .send((result, std::mem::take(&mut context.stats)));
+            }
+            WorkerMessage::NewGame => {
+                if let Some(context) = context.as_mut() {
+                    context.clear_move_ordering();
+                }
+            }
+            WorkerMessage::Exit => return,
         }
+    }
+}
 
-        search_outcome
+// Only the principal thread reports its progress and checks the node and time
+// limits, the other threads are stopped through the shared stop flag
+pub struct SearchGroup {
+    principal: Receiver<(AgentResult, Stats)>,
+    principal_result: RefCell<Option<Result<(AgentResult, Stats), SearchGroupError>>>,
+    agents: Vec<Receiver<(AgentResult, Stats)>>,
+    stop_flag: Arc<AtomicBool>,
+    completion: Arc<Completion>,
+    // The statistics of all threads are reported together
+    #[cfg(feature = "stats")]
+    info_sender: Sender<OutputEvent>,
+}
+
+impl SearchGroup {
+    pub fn stop(self) -> Result<SearchOutcome, SearchGroupError> {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        self.await_principal()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        let mut principal_result = self.principal_result.borrow_mut();
+        if principal_result.is_none() {
+            *principal_result = match self.principal.try_recv() {
+                Ok(result) => Some(Ok(result)),
+                Err(TryRecvError::Disconnected) => Some(Err(SearchGroupError::ThreadPanicked)),
+                Err(TryRecvError::Empty) => None,
+            };
+        }
+        principal_result.is_some()
+    }
+
+    pub fn await_principal(self) -> Result<SearchOutcome, SearchGroupError> {
+        // A worker that panics drops its result sender without sending
+        let principal_result = match self.principal_result.take() {
+            Some(result) => result,
+            None => self
+                .principal
+                .recv()
+                .map_err(|_| SearchGroupError::ThreadPanicked),
+        };
+
+        // The helpers are stopped and joined even if the principal failed, so
+        // the pool is idle again when this returns
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let mut results = vec![principal_result?];
+        // A failed helper does not invalidate the search, its vote is just lost
+        results.extend(self.agents.iter().filter_map(|agent| agent.recv().ok()));
+
+        #[cfg(feature = "stats")]
+        self.report_stats(&results);
+        let results: Vec<AgentResult> = results.into_iter().map(|(result, _)| result).collect();
+        Ok(select_best_result(&results).outcome)
+    }
+
+    // Sent before the best move, after all threads are done, and only in
+    // debug mode so the output of a normal search stays as without the feature
+    #[cfg(feature = "stats")]
+    fn report_stats(&self, results: &[(AgentResult, Stats)]) {
+        if !debug_enabled() {
+            return;
+        }
+        let mut total = SearchStats::default();
+        for (_, stats) in results {
+            total.merge(stats);
+        }
+        let _ = self
+            .info_sender
+            .send(OutputEvent::Raw(format!("info string stats {total}")));
     }
 }
 
-fn create_search_context(
-    info_sender: Sender<SearchInfo>,
-    position: &Position,
-    hash_table: TranspositionTable,
-) -> (SearchContext, Sender<bool>) {
-    let (stop_sender, stop_receiver) = channel();
+// A handle to the engine that can be shared between threads, at most one
+// search runs at a time. Searches are collected with wait or stop, which
+// hand out the result of a search exactly once.
+pub struct SearchEngine {
+    state: Mutex<EngineState>,
+}
 
-    let mut search_context = SearchContext::new(
-        position.board,
-        stop_receiver,
-        info_sender.clone(),
-        hash_table, //Arc::clone(&hash_table)
-    );
-    for hash in position.hash_history.iter() {
-        search_context.set_visited(*hash);
+struct EngineState {
+    pool: ThreadPool,
+    search: Option<SearchGroup>,
+    finished_callback: Option<FinishedCallback>,
+}
+
+impl SearchEngine {
+    pub fn new(num_threads: u8, table_size: u32) -> SearchEngine {
+        SearchEngine {
+            state: Mutex::new(EngineState {
+                pool: ThreadPool::new(num_threads, table_size),
+                search: None,
+                finished_callback: None,
+            }),
+        }
+    }
+
+    // Applies new settings, which is only possible between searches
+    pub fn configure(&self, num_threads: u8, table_size: u32) -> Result<(), SearchGroupError> {
+        if num_threads == 0 {
+            return Err(SearchGroupError::InvalidParameters(
+                "at least one thread is needed".to_string(),
+            ));
+        }
+
+        let mut state = self.idle_state()?;
+        state.pool.resize(num_threads);
+        state.pool.set_table_size(table_size);
+        Ok(())
     }
 
-    (search_context, stop_sender)
+    pub fn set_network(&self, network: Option<Arc<Network>>) -> Result<(), SearchGroupError> {
+        self.idle_state()?.pool.set_network(network);
+        Ok(())
+    }
+
+    #[cfg(feature = "tune")]
+    pub fn set_eval_params(&self, eval_params: Arc<EvalParams>) -> Result<(), SearchGroupError> {
+        self.idle_state()?.pool.set_eval_params(eval_params);
+        Ok(())
+    }
+
+    pub fn new_game(&self) -> Result<(), SearchGroupError> {
+        self.idle_state()?.pool.new_game();
+        Ok(())
+    }
+
+    // Called at the end of every following search, also of stopped ones
+    pub fn set_finished_callback(&self, callback: FinishedCallback) {
+        self.lock().finished_callback = Some(callback);
+    }
+
+    pub fn start(
+        &self,
+        position: &Position,
+        info_sender: Sender<OutputEvent>,
+        max_depth: SearchDepth,
+        deadline: Option<Instant>,
+        node_limit: Option<u64>,
+        root_moves: &[ChessMove],
+    ) -> Result<(), SearchGroupError> {
+        let mut state = self.idle_state()?;
+        let group = state.pool.start(
+            position,
+            info_sender,
+            max_depth,
+            deadline,
+            node_limit,
+            root_moves,
+        )?;
+        state.run(group);
+        Ok(())
+    }
+
+    #[cfg(feature = "tree")]
+    pub fn start_tree(
+        &self,
+        position: &Position,
+        info_sender: Sender<OutputEvent>,
+        depth: SearchDepth,
+        plies: usize,
+        format: TreeFormat,
+    ) -> Result<(), SearchGroupError> {
+        let mut state = self.idle_state()?;
+        let group = state
+            .pool
+            .start_tree(position, info_sender, depth, plies, format)?;
+        state.run(group);
+        Ok(())
+    }
+
+    // Whether a search was started and not collected yet
+    pub fn is_running(&self) -> bool {
+        self.lock().search.is_some()
+    }
+
+    // Whether the running search is done and wait returns immediately
+    pub fn is_finished(&self) -> bool {
+        self.lock()
+            .search
+            .as_ref()
+            .is_some_and(|group| group.is_finished())
+    }
+
+    pub fn stop(&self) -> Result<SearchOutcome, SearchGroupError> {
+        let group = self
+            .lock()
+            .search
+            .take()
+            .ok_or(SearchGroupError::NotRunning)?;
+        group.stop()
+    }
+
+    // Blocks until the search ends by itself. The lock is not held while
+    // waiting, so another thread can still stop the search.
+    pub fn wait(&self) -> Result<SearchOutcome, SearchGroupError> {
+        let completion = self
+            .lock()
+            .search
+            .as_ref()
+            .map(|group| group.completion.clone())
+            .ok_or(SearchGroupError::NotRunning)?;
+        completion.wait();
+
+        let group = self
+            .lock()
+            .search
+            .take()
+            .ok_or(SearchGroupError::NotRunning)?;
+        group.await_principal()
+    }
+
+    pub fn shutdown(self) {
+        let state = self
+            .state
+            .into_inner()
+            .unwrap_or_else(|error| error.into_inner());
+        if let Some(group) = state.search {
+            let _ = group.stop();
+        }
+        state.pool.shutdown();
+    }
+
+    fn idle_state(&self) -> Result<MutexGuard<'_, EngineState>, SearchGroupError> {
+        let state = self.lock();
+        match state.search {
+            Some(_) => Err(SearchGroupError::AlreadyRunning),
+            None => Ok(state),
+        }
+    }
+
+    // A panic while holding the lock can not leave the state inconsistent,
+    // so a poisoned lock is simply taken over
+    fn lock(&self) -> MutexGuard<'_, EngineState> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
 }
 
-struct SearchAgent {
-    pub stop: Sender<bool>,
-    pub handle: JoinHandle<SearchOutcome>,
+impl EngineState {
+    fn run(&mut self, group: SearchGroup) {
+        if let Some(callback) = self.finished_callback.clone() {
+            group.completion.call_when_finished(callback);
+        }
+        self.search = Some(group);
+    }
 }
 
-impl SearchAgent {
-    fn start(
-        mut context: SearchContext,
-        stop_sender: Sender<bool>,
-        max_depth: u8,
-        time_limit: Option<f32>,
-    ) -> SearchAgent {
-        assert!(time_limit.is_none(), "time limit not implemented yet");
+// The result of one thread, together with the depth of its last completed
+// iteration, which is 0 if not even the first iteration was finished
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AgentResult {
+    pub outcome: SearchOutcome,
+    pub depth: SearchDepth,
+}
 
-        SearchAgent {
-            stop: stop_sender,
-            handle: thread::spawn(move || context.root_search(max_depth)),
+// Every thread votes for its best move, weighted by its score relative to the
+// worst thread and by its completed depth. The move with the most votes wins,
+// unless a thread has proven a mate, then the fastest mate is played.
+// results[0] is the principal thread, which wins ties.
+pub fn select_best_result(results: &[AgentResult]) -> AgentResult {
+    let completed: Vec<&AgentResult> = results.iter().filter(|result| result.depth > 0).collect();
+    let Some(&&first) = completed.first() else {
+        return results[0];
+    };
+
+    let min_score = completed
+        .iter()
+        .map(|result| result.outcome.0)
+        .min()
+        .unwrap_or(0);
+
+    let mut votes: Vec<(ChessMove, i64)> = vec![];
+    for result in &completed {
+        let weight = (result.outcome.0 - min_score + VOTE_OFFSET) as i64 * result.depth as i64;
+        match votes.iter_mut().find(|(m, _)| *m == result.outcome.1) {
+            Some((_, count)) => *count += weight,
+            None => votes.push((result.outcome.1, weight)),
         }
     }
+    let votes_for = |chess_move: ChessMove| {
+        votes
+            .iter()
+            .find(|(m, _)| *m == chess_move)
+            .map_or(0, |(_, count)| *count)
+    };
 
-    fn stop(self, n_signals: u32) -> Result<SearchOutcome, ()> {
-        send_termination_signals(self.stop, n_signals);
-        match self.handle.join() {
-            Ok(outcome) => Ok(outcome),
-            Err(_) => Err(()),
+    let mut best = first;
+    for &&result in &completed[1..] {
+        let better = match best.outcome.0 > config::MATE_THRESHOLD {
+            true => result.outcome.0 > best.outcome.0,
+            false => {
+                result.outcome.0 > config::MATE_THRESHOLD
+                    || votes_for(result.outcome.1) > votes_for(best.outcome.1)
+            }
+        };
+        if better {
+            best = result;
         }
     }
+    best
+}
+
+pub(crate) fn new_hash_table(table_size: u32) -> TranspositionTable {
+    TranspositionTable::new(
+        table_size as usize,
+        TableEntryData {
+            best_move: ChessMove::new(Square::A1, Square::A1, None),
+            score: 0,
+            depth: 0,
+            score_bound: ScoreBound::LowerBound,
+        },
+    )
 }
 
-fn send_termination_signals(sender: Sender<bool>, n_signals: u32) {
-    for _ in 0..n_signals {
-        let _ = sender.send(STOP_SIGNAL);
+fn create_search_context(thread_index: usize, job: &SearchJob) -> SearchContext {
+    let mut search_context = SearchContext::new(
+        job.position.board,
+        job.stop_flag.clone(),
+        job.info_sender.clone(),
+        job.hash_table.clone(),
+    );
+    if thread_index > 0 {
+        search_context.capture_order = ORDERINGS[(thread_index - 1) % ORDERINGS.len()];
+    }
+    search_context.thread_index = thread_index;
+    prepare_search_context(&mut search_context, job);
+
+    search_context
+}
+
+fn prepare_search_context(search_context: &mut SearchContext, job: &SearchJob) {
+    search_context.reset_search(job.position.board);
+    search_context.stop_flag = job.stop_flag.clone();
+    search_context.sender_channel = job.info_sender.clone();
+    search_context.hash_table = job.hash_table.clone();
+    search_context.deadline = job.deadline;
+    search_context.node_limit = job.node_limit;
+    search_context.evaluator.set_network(job.network.clone());
+    #[cfg(feature = "tune")]
+    search_context.evaluator.set_params(job.eval_params.clone());
+
+    for hash in job.position.repetition_hashes() {
+        search_context.set_visited(*hash);
     }
+    search_context.castling = job.position.search_castling();
+    search_context.root_moves = job.root_moves.clone();
 }
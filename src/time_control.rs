@@ -0,0 +1,32 @@
+use chess::Color;
+use std::time::Duration;
+
+use crate::config;
+use crate::uci_command::SearchLimits;
+
+// How long a search may take under the limits of a "go" command. None means
+// the search runs until it is stopped or reaches its depth or node limit.
+pub fn allocate_time(limits: &SearchLimits, side_to_move: Color) -> Option<Duration> {
+    if limits.infinite {
+        return None;
+    }
+
+    if let Some(movetime) = limits.movetime {
+        return Some(Duration::from_millis(movetime));
+    }
+
+    let (time_left, increment) = match side_to_move {
+        Color::White => (limits.wtime?, limits.winc.unwrap_or(0)),
+        Color::Black => (limits.btime?, limits.binc.unwrap_or(0)),
+    };
+    let moves_to_go = limits
+        .movestogo
+        .unwrap_or(config::DEFAULT_MOVES_TO_GO)
+        .max(1) as u64;
+
+    // A share of the clock plus most of the increment, but never the whole clock
+    let budget = (time_left / moves_to_go).saturating_add(increment / 4 * 3);
+    let maximum = time_left.saturating_sub(config::MOVE_OVERHEAD_MS);
+
+    Some(Duration::from_millis(budget.min(maximum)))
+}
@@ -0,0 +1,329 @@
+use chess::ChessMove;
+use std::fmt::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+
+use crate::config;
+use crate::position::Position;
+use crate::printer::{format_move, format_score};
+use crate::search::{PositionScore, SearchContext, SearchDepth, SearchOutcome};
+use crate::table::ScoreBound;
+use crate::threading::new_hash_table;
+
+// Recording of the search tree for debugging, only compiled with the "tree"
+// feature. A SearchContext records nodes while its tree is set, the recording
+// stops at max_plies below the root.
+
+// A recorded tree is only readable for small depths, which need no big table
+const TABLE_SIZE: u32 = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TreeFormat {
+    Dot,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeReason {
+    // All moves were searched without a beta cutoff
+    Searched,
+    BetaCutoff,
+    TableCutoff,
+    Repetition,
+    StandPat,
+    Checkmate,
+    Stalemate,
+    Stopped,
+}
+
+impl NodeReason {
+    fn name(&self) -> &'static str {
+        match self {
+            NodeReason::Searched => "searched",
+            NodeReason::BetaCutoff => "beta cutoff",
+            NodeReason::TableCutoff => "tt cutoff",
+            NodeReason::Repetition => "repetition",
+            NodeReason::StandPat => "stand pat",
+            NodeReason::Checkmate => "checkmate",
+            NodeReason::Stalemate => "stalemate",
+            NodeReason::Stopped => "stopped",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TreeNode {
+    // None for the root
+    pub chess_move: Option<ChessMove>,
+    pub alpha: PositionScore,
+    pub beta: PositionScore,
+    pub depth: SearchDepth,
+    pub quiescence: bool,
+    pub table_hit: bool,
+    pub score: Option<PositionScore>,
+    pub bound: Option<ScoreBound>,
+    pub reason: NodeReason,
+    pub children: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchTree {
+    pub max_plies: usize,
+    // The root of the last iteration is the first node
+    pub nodes: Vec<TreeNode>,
+    stack: Vec<usize>,
+    ply: usize,
+}
+
+impl SearchTree {
+    pub fn new(max_plies: usize) -> SearchTree {
+        SearchTree {
+            max_plies,
+            nodes: vec![],
+            stack: vec![],
+            ply: 0,
+        }
+    }
+
+    // Every iteration of the iterative deepening starts a new tree
+    pub fn start_iteration(&mut self, depth: SearchDepth) {
+        self.nodes.clear();
+        self.stack.clear();
+        self.ply = 0;
+        self.nodes.push(TreeNode::new(
+            None,
+            -config::INFINITY,
+            config::INFINITY,
+            depth,
+        ));
+        self.stack.push(0);
+    }
+
+    pub fn enter(
+        &mut self,
+        chess_move: ChessMove,
+        alpha: PositionScore,
+        beta: PositionScore,
+        depth: SearchDepth,
+    ) {
+        self.ply += 1;
+        if !self.records() {
+            return;
+        }
+        let Some(&parent) = self.stack.last() else {
+            return;
+        };
+
+        let index = self.nodes.len();
+        self.nodes
+            .push(TreeNode::new(Some(chess_move), alpha, beta, depth));
+        self.nodes[parent].children.push(index);
+        self.stack.push(index);
+    }
+
+    // The score is seen from the side to move of the node, the bound follows
+    // from the window the node was searched with
+    pub fn exit(&mut self, score: PositionScore) {
+        if self.records() && self.ply > 0 {
+            if let Some(index) = self.stack.pop() {
+                self.nodes[index].set_score(score);
+            }
+        }
+        self.ply = self.ply.saturating_sub(1);
+    }
+
+    pub fn finish_root(&mut self, score: PositionScore) {
+        if let Some(root) = self.nodes.first_mut() {
+            root.set_score(score);
+        }
+    }
+
+    pub fn set_reason(&mut self, reason: NodeReason) {
+        if let Some(node) = self.current() {
+            node.reason = reason;
+        }
+    }
+
+    pub fn set_table_hit(&mut self) {
+        if let Some(node) = self.current() {
+            node.table_hit = true;
+        }
+    }
+
+    pub fn set_quiescence(&mut self) {
+        if let Some(node) = self.current() {
+            node.quiescence = true;
+        }
+    }
+
+    pub fn export(&self, format: TreeFormat) -> String {
+        match format {
+            TreeFormat::Dot => self.to_dot(),
+            TreeFormat::Json => self.to_json(),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph search {\n    node [shape=box];\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let _ = writeln!(
+                dot,
+                "    n{index} [label=\"{}\\n[{}, {}] d{}{}\\n{} {}{}\"];",
+                node.chess_move.map_or("root".to_string(), format_move),
+                window_bound(node.alpha),
+                window_bound(node.beta),
+                node.depth,
+                if node.quiescence { " qs" } else { "" },
+                node.score.map_or("-".to_string(), format_score),
+                bound_name(node.bound),
+                reason_label(node),
+            );
+            for child in &node.children {
+                let _ = writeln!(dot, "    n{index} -> n{child};");
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Nested objects starting at the root, written by hand as the values are
+    // only numbers and fixed names
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        if !self.nodes.is_empty() {
+            self.write_json(&mut json, 0);
+        }
+        json
+    }
+
+    fn write_json(&self, json: &mut String, index: usize) {
+        let node = &self.nodes[index];
+        let _ = write!(
+            json,
+            "{{\"move\":{},\"alpha\":{},\"beta\":{},\"depth\":{},\"quiescence\":{},\
+             \"tt_hit\":{},\"score\":{},\"bound\":\"{}\",\"reason\":\"{}\",\"children\":[",
+            node.chess_move
+                .map_or("null".to_string(), |chess_move| format!(
+                    "\"{}\"",
+                    format_move(chess_move)
+                )),
+            node.alpha,
+            node.beta,
+            node.depth,
+            node.quiescence,
+            node.table_hit,
+            node.score
+                .map_or("null".to_string(), |score| score.to_string()),
+            bound_name(node.bound),
+            node.reason.name(),
+        );
+        for (position, child) in node.children.iter().enumerate() {
+            if position > 0 {
+                json.push(',');
+            }
+            self.write_json(json, *child);
+        }
+        json.push_str("]}");
+    }
+
+    #[inline]
+    fn records(&self) -> bool {
+        self.ply <= self.max_plies
+    }
+
+    #[inline]
+    fn current(&mut self) -> Option<&mut TreeNode> {
+        match self.records() {
+            true => self.stack.last().map(|index| &mut self.nodes[*index]),
+            false => None,
+        }
+    }
+}
+
+impl TreeNode {
+    fn new(
+        chess_move: Option<ChessMove>,
+        alpha: PositionScore,
+        beta: PositionScore,
+        depth: SearchDepth,
+    ) -> TreeNode {
+        TreeNode {
+            chess_move,
+            alpha,
+            beta,
+            depth,
+            quiescence: false,
+            table_hit: false,
+            score: None,
+            bound: None,
+            reason: NodeReason::Searched,
+            children: vec![],
+        }
+    }
+
+    fn set_score(&mut self, score: PositionScore) {
+        self.score = Some(score);
+        self.bound = Some(if score <= self.alpha {
+            ScoreBound::UpperBound
+        } else if score >= self.beta {
+            ScoreBound::LowerBound
+        } else {
+            ScoreBound::Exact
+        });
+    }
+}
+
+// The initial window is printed as infinite instead of as a mate score
+fn window_bound(score: PositionScore) -> String {
+    match score {
+        config::INFINITY => "inf".to_string(),
+        score if score == -config::INFINITY => "-inf".to_string(),
+        score => format_score(score),
+    }
+}
+
+fn bound_name(bound: Option<ScoreBound>) -> &'static str {
+    match bound {
+        Some(ScoreBound::Exact) => "exact",
+        Some(ScoreBound::LowerBound) => "lower",
+        Some(ScoreBound::UpperBound) => "upper",
+        None => "none",
+    }
+}
+
+fn reason_label(node: &TreeNode) -> String {
+    match node.table_hit && node.reason != NodeReason::TableCutoff {
+        true => format!(" {}, tt hit", node.reason.name()),
+        false => format!(" {}", node.reason.name()),
+    }
+}
+
+// Searches the position single threaded to the given depth and records the
+// tree of the last iteration up to max_plies below the root. This runs outside
+// of the engine, which records trees with ThreadPool::start_tree.
+pub fn record_search_tree(
+    position: &Position,
+    depth: SearchDepth,
+    max_plies: usize,
+) -> (SearchOutcome, SearchTree) {
+    let (sender, _) = mpsc::channel();
+    let mut search_context = SearchContext::new(
+        position.board,
+        Arc::new(AtomicBool::new(false)),
+        sender,
+        new_hash_table(TABLE_SIZE),
+    );
+    for hash in position.repetition_hashes() {
+        search_context.set_visited(*hash);
+    }
+    search_context.castling = position.search_castling();
+    search_context.tree = Some(SearchTree::new(max_plies));
+
+    let outcome = search_context.root_search(depth.clamp(1, config::MAX_DEPTH));
+    let tree = search_context
+        .tree
+        .take()
+        .unwrap_or_else(|| SearchTree::new(max_plies));
+
+    (outcome, tree)
+}
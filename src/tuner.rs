@@ -0,0 +1,247 @@
+use chess::{Board, Color};
+use serde_json::Value;
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use crate::config;
+use crate::epd::epd_board;
+use crate::params::EvalParams;
+use crate::search::{PositionScore, SearchContext};
+use crate::threading::new_hash_table;
+
+// Texel tuning: the evaluation is fitted to game results by minimising the
+// squared error between the results and the quiescence scores mapped through
+// a sigmoid. The parameters are improved by local search, every single value
+// is moved by one as long as the error decreases.
+
+pub struct LabelledPosition {
+    pub board: Board,
+    // From white's point of view: 1 for a win, 0.5 for a draw and 0 for a loss
+    pub result: f64,
+}
+
+// Reads an EPD or FEN line with the game result either as an opcode like
+// c9 "1-0" or as a trailing token like "1/2-1/2" or [0.5]
+pub fn parse_labelled_position(line: &str) -> Option<LabelledPosition> {
+    let board = epd_board(line)?;
+    let tokens: Vec<&str> = line.split_whitespace().skip(4).collect();
+    let result = tokens.into_iter().rev().find_map(parse_result)?;
+
+    Some(LabelledPosition { board, result })
+}
+
+fn parse_result(token: &str) -> Option<f64> {
+    let result = token.trim_matches(|c| c == '"' || c == ';');
+    match result {
+        "1-0" => Some(1.0),
+        "0-1" => Some(0.0),
+        "1/2-1/2" => Some(0.5),
+        // A plain number could be a move counter, so it has to be in brackets
+        _ => result
+            .strip_prefix('[')
+            .and_then(|result| result.strip_suffix(']'))
+            .and_then(|result| match result {
+                "1-0" | "0-1" | "1/2-1/2" => parse_result(result),
+                result => result.parse().ok(),
+            })
+            .filter(|result: &f64| (0.0..=1.0).contains(result)),
+    }
+}
+
+// All numbers of the parameters in a fixed order
+pub fn flatten(params: &EvalParams) -> Vec<i32> {
+    let mut values = vec![];
+    collect_numbers(
+        &serde_json::to_value(params).unwrap_or_default(),
+        &mut values,
+    );
+    values
+}
+
+// The parameters with the numbers replaced in the order of flatten
+pub fn unflatten(params: &EvalParams, values: &[i32]) -> EvalParams {
+    let mut fields = serde_json::to_value(params).unwrap_or_default();
+    replace_numbers(&mut fields, &mut values.iter());
+    serde_json::from_value(fields).unwrap_or_else(|_| params.clone())
+}
+
+fn collect_numbers(value: &Value, values: &mut Vec<i32>) {
+    match value {
+        Value::Number(number) => values.push(number.as_i64().unwrap_or_default() as i32),
+        Value::Array(items) => items.iter().for_each(|item| collect_numbers(item, values)),
+        Value::Object(fields) => fields
+            .values()
+            .for_each(|field| collect_numbers(field, values)),
+        _ => {}
+    }
+}
+
+fn replace_numbers<'a>(value: &mut Value, values: &mut impl Iterator<Item = &'a i32>) {
+    match value {
+        Value::Number(_) => {
+            if let Some(number) = values.next() {
+                *value = (*number).into();
+            }
+        }
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| replace_numbers(item, values)),
+        Value::Object(fields) => fields
+            .values_mut()
+            .for_each(|field| replace_numbers(field, values)),
+        _ => {}
+    }
+}
+
+#[inline]
+fn sigmoid(score: PositionScore, scaling: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scaling * score as f64 / 400.0))
+}
+
+pub struct Tuner {
+    positions: Vec<LabelledPosition>,
+    // Every thread scores its share of the positions with its own context
+    contexts: Vec<SearchContext>,
+    pub scaling: f64,
+}
+
+impl Tuner {
+    pub fn new(positions: Vec<LabelledPosition>, threads: usize) -> Tuner {
+        let contexts = (0..threads.max(1))
+            .map(|_| {
+                let (sender, _) = mpsc::channel();
+                // Quiescence search only probes the table and never stores,
+                // so it stays empty and no score of other parameters is found
+                SearchContext::new(
+                    Board::default(),
+                    Arc::new(AtomicBool::new(false)),
+                    sender,
+                    new_hash_table(1),
+                )
+            })
+            .collect();
+
+        Tuner {
+            positions,
+            contexts,
+            scaling: 1.0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // The quiescence scores of all positions from white's point of view
+    pub fn scores(&mut self, params: &EvalParams) -> Vec<PositionScore> {
+        let params = Arc::new(params.clone());
+        let chunk_size = self.positions.len().div_ceil(self.contexts.len()).max(1);
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .contexts
+                .iter_mut()
+                .zip(self.positions.chunks(chunk_size))
+                .map(|(context, positions)| {
+                    context.evaluator.set_params(params.clone());
+                    scope.spawn(move || {
+                        positions
+                            .iter()
+                            .map(|position| {
+                                let score = context.quiescence_search(
+                                    &position.board,
+                                    -config::INFINITY,
+                                    config::INFINITY,
+                                );
+                                match position.board.side_to_move() {
+                                    Color::White => score,
+                                    Color::Black => -score,
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Tuner thread panicked"))
+                .collect()
+        })
+    }
+
+    pub fn error(&mut self, params: &EvalParams) -> f64 {
+        let scores = self.scores(params);
+        self.error_of_scores(&scores, self.scaling)
+    }
+
+    fn error_of_scores(&self, scores: &[PositionScore], scaling: f64) -> f64 {
+        let total: f64 = self
+            .positions
+            .iter()
+            .zip(scores)
+            .map(|(position, score)| (position.result - sigmoid(*score, scaling)).powi(2))
+            .sum();
+        total / self.positions.len().max(1) as f64
+    }
+
+    // The scaling of the sigmoid that fits the given parameters best, found
+    // by a golden section search
+    pub fn fit_scaling(&mut self, params: &EvalParams) -> f64 {
+        let scores = self.scores(params);
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (0.0, 5.0);
+
+        for _ in 0..40 {
+            let left = high - ratio * (high - low);
+            let right = low + ratio * (high - low);
+            if self.error_of_scores(&scores, left) < self.error_of_scores(&scores, right) {
+                high = right;
+            } else {
+                low = left;
+            }
+        }
+        self.scaling = (low + high) / 2.0;
+        self.scaling
+    }
+
+    // Runs at most the given number of passes over all values and stops early
+    // when a pass brings no improvement. The progress callback gets the pass
+    // and the error after it.
+    pub fn tune<F: FnMut(usize, f64, &EvalParams)>(
+        &mut self,
+        params: &EvalParams,
+        passes: usize,
+        mut progress: F,
+    ) -> EvalParams {
+        let mut values = flatten(params);
+        let mut best_error = self.error(params);
+
+        for pass in 1..=passes {
+            let mut improved = false;
+            for index in 0..values.len() {
+                for step in [1, -1] {
+                    values[index] += step;
+                    let error = self.error(&unflatten(params, &values));
+                    if error < best_error {
+                        best_error = error;
+                        improved = true;
+                        break;
+                    }
+                    values[index] -= step;
+                }
+            }
+
+            progress(pass, best_error, &unflatten(params, &values));
+            if !improved {
+                break;
+            }
+        }
+        unflatten(params, &values)
+    }
+}
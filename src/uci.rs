@@ -1,278 +1,278 @@
-use chess::{ChessMove, Square};
-use log::{debug, error, info, warn};
-use std::str::FromStr;
+use chess::ChessMove;
+use log::{debug, info};
+use std::io::{self, BufRead, BufReader, Write};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::thread::{sleep, JoinHandle};
-use std::{io, thread, time};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 use crate::config;
-use crate::search::{SearchInfo, SearchOutcome};
-use crate::threading::SearchGroup;
-
-#[derive(Clone)]
-// TODO: Change this code to use the Game Struct from chess crate
-pub struct Position {
-    pub board: chess::Board,
-    pub hash_history: Vec<u64>,
-}
-
-struct Printer {
-    str_sender: Sender<String>,
-    info_sender: Sender<SearchInfo>,
-    bestmove_sender: Sender<ChessMove>,
-    stop_sender: Sender<bool>,
-
-    handle: JoinHandle<()>,
-}
-
-impl Printer {
-    fn print(self, text: &str) -> Printer {
-        let _ = self.str_sender.send(text.to_string());
-        self
-    }
-
-    fn result(self, result: SearchOutcome) -> Printer {
-        let _ = self
-            .info_sender
-            .send((result.0, result.1, config::MAX_DEPTH + 1));
-        self
-    }
-
-    fn bestmove(self, best_move: ChessMove) -> Printer {
-        let _ = self.bestmove_sender.send(best_move);
-        self
-    }
-
-    fn stop(self) {
-        let _ = self.stop_sender.send(true);
-        let _ = self.handle.join();
-    }
+use crate::options::EngineOptions;
+pub use crate::position::Position;
+use crate::printer::{attach_logger, set_debug, Printer};
+use crate::search::{terminal_outcome, SearchDepth, SearchOutcome};
+use crate::threading::{SearchEngine, SearchGroupError};
+use crate::time_control::allocate_time;
+use crate::uci_command::{parse_command, parse_position, SearchLimits, UciCommand, UciError};
+
+pub(crate) const QUIT_COMMAND: &str = "quit";
+
+// The protocol loops block on a single channel, which delivers both the input
+// lines and the end of a search
+pub(crate) enum LoopEvent {
+    Input(String),
+    SearchFinished,
 }
 
-struct PrinterReceiver {
-    str: Receiver<String>,
-    info: Receiver<SearchInfo>,
-    bestmove: Receiver<ChessMove>,
-    stop: Receiver<bool>,
-}
-
-fn build_printer() -> Printer {
-    let (print_sender, print_receiver) = channel();
-    let (info_sender, info_receiver) = channel();
-    let (bestmove_sender, bestmove_reveicer) = channel();
-    let (stop_sender, stop_receiver) = channel();
-
-    let receiver = PrinterReceiver {
-        str: print_receiver,
-        info: info_receiver,
-        bestmove: bestmove_reveicer,
-        stop: stop_receiver,
-    };
-
-    let handle = thread::spawn(move || printing_loop(receiver));
-
-    Printer {
-        str_sender: print_sender,
-        info_sender,
-        bestmove_sender,
-        stop_sender,
-        handle,
-    }
+pub fn uci_mode() {
+    uci_loop(BufReader::new(io::stdin()), io::stdout());
 }
 
-pub fn uci_mode() {
+// Runs the protocol on arbitrary streams, so the whole command handling can be
+// driven in-process by tests. Returns after "quit" or when the input is closed.
+pub fn uci_loop<R, W>(input: R, output: W)
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
     info!("uci mode started\n");
 
-    let mut position = Position {
-        board: chess::Board::default(),
-        hash_history: vec![],
-    };
+    let mut position = Position::default();
 
-    let mut printer = build_printer();
-    let mut search_group: Option<SearchGroup> = None;
+    let printer = Printer::start(output);
+    attach_logger(&printer);
+    set_debug(false);
+
+    let mut options = EngineOptions::default();
+    let engine = SearchEngine::new(options.threads, options.hash_table_size());
+    let events = spawn_input_reader(input, &engine);
 
     loop {
-        let input_line = collect_user_input();
+        let input_line = match events.recv() {
+            Ok(LoopEvent::Input(line)) => line,
+            // The event of a search that was already collected by "stop" is
+            // ignored, the running search would not be finished
+            Ok(LoopEvent::SearchFinished) => {
+                if engine.is_finished() {
+                    report_outcome(engine.wait(), &printer);
+                }
+                continue;
+            }
+            Err(_) => QUIT_COMMAND.to_string(),
+        };
         info!("{}\n", input_line);
 
-        let input: Vec<&str> = input_line.split(" ").collect();
-        let command = input[0];
-        let arguments = &input[1..];
-        info!("{}\n", command);
+        let command = match parse_command(&input_line) {
+            Ok(command) => command,
+            Err(UciError::EmptyCommand) => continue,
+            Err(parse_error) => {
+                printer.print(&format!("info string {parse_error}"));
+                continue;
+            }
+        };
 
-        if command == "uci" {
-            printer = printer.print("uciok");
-        } else if command == "isready" {
-            printer = printer.print("readyok");
-        } else if command == "ucinewgame" {
-            position = Position {
-                board: chess::Board::default(),
-                hash_history: vec![],
-            };
-        } else if command == "position" {
-            position = change_position(arguments);
-        } else if command == "stop" {
-            if search_group.is_some() {
-                let result = search_group.unwrap().stop();
-                search_group = None;
-                match result {
-                    Ok(outcome) => {
-                        info!("stop; search result: {} - {}\n", outcome.0, outcome.1);
-                        printer = printer.result(outcome);
-                        printer = printer.bestmove(outcome.1);
-                    }
-                    Err(()) => {
-                        error!("stop; group.stop() failed!")
-                    }
+        match command {
+            UciCommand::Uci => {
+                printer.print(&format!("id name {}", config::ENGINE_NAME));
+                printer.print(&format!("id author {}", config::ENGINE_AUTHOR));
+                for option_line in options.uci_option_lines() {
+                    printer.print(&option_line);
+                }
+                printer.print("uciok");
+            }
+            UciCommand::Debug(enabled) => set_debug(enabled),
+            UciCommand::IsReady => printer.print("readyok"),
+            UciCommand::SetOption { name, value } => {
+                // The classical fallback is reported once, when the setting that
+                // causes it is made, not on every search
+                let missing_network = options.missing_network();
+                match options.set_option(&name, value.as_deref()) {
+                    Err(option_error) => printer.print(&format!("info string {option_error}")),
+                    Ok(()) if !missing_network && options.missing_network() => printer.print(
+                        "info string UseNNUE without an EvalFile, using the classical evaluation",
+                    ),
+                    Ok(()) => {}
                 }
-            } else {
-                warn!("stop; no search_group")
             }
-        } else if command == "quit" {
-            printer.stop();
+            UciCommand::Register => {}
+            UciCommand::UciNewGame => {
+                // A new game invalidates the running search, its result is still
+                // reported so the GUI receives the bestmove it is waiting for
+                report_outcome(engine.stop(), &printer);
+                report_error(engine.new_game(), &printer);
+                position = Position::default();
+                let _ = position.set_chess960(options.chess960);
+            }
+            UciCommand::Position { mut start, moves } => {
+                // The search works on its own copy of the position, so changing it
+                // while searching only affects the next "go"
+                let updated = start
+                    .set_chess960(options.chess960)
+                    .and_then(|_| position.update(&start, &moves));
+                if let Err(position_error) = updated {
+                    printer.print(&format!("info string {position_error}"));
+                }
+            }
+            UciCommand::Stop => report_outcome(engine.stop(), &printer),
+            UciCommand::Quit => {
+                engine.shutdown();
+                printer.stop();
 
-            match search_group {
-                Some(group) => match group.stop() {
-                    Err(_) => {
-                        error!("quit; group.stop() Error!")
+                info!("shutting down");
+                return;
+            }
+            UciCommand::Go(limits) => {
+                info!("start search");
+                let configured = configure_engine(&engine, &options);
+                let root_moves = legal_search_moves(&limits, &position, &printer);
+                let started = configured.and_then(|_| {
+                    engine.start(
+                        &position,
+                        printer.sender(),
+                        search_depth(&limits),
+                        allocate_time(&limits, position.board.side_to_move())
+                            .and_then(|time| Instant::now().checked_add(time)),
+                        limits.nodes,
+                        &root_moves,
+                    )
+                });
+                match started {
+                    // A mated or stalemated position is answered right away
+                    Err(SearchGroupError::NoLegalMoves) => {
+                        printer.bestmove(terminal_outcome(&position.board))
                     }
-                    Ok(_) => {
-                        debug! {"quit; group.stop() Ok"}
+                    _ => report_error(started, &printer),
+                }
+            }
+            // Runs like a search on the engine and can be stopped, the tree is
+            // printed before the bestmove
+            #[cfg(feature = "tree")]
+            UciCommand::Tree {
+                depth,
+                plies,
+                format,
+            } => {
+                let started = configure_engine(&engine, &options).and_then(|_| {
+                    engine.start_tree(&position, printer.sender(), depth, plies, format)
+                });
+                match started {
+                    Err(SearchGroupError::NoLegalMoves) => {
+                        printer.bestmove(terminal_outcome(&position.board))
                     }
-                },
-                None => debug! {"quit; no search_group"},
+                    _ => report_error(started, &printer),
+                }
             }
-
-            info!("shutting down");
-            return;
-        } else if command == "go" {
-            info!("start search");
-            if search_group.is_none() {
-                search_group = Some(SearchGroup::start(
-                    position.clone(),
-                    config::THREAD_COUNT,
-                    printer.info_sender.clone(),
-                    config::HASH_TABLE_SIZE,
-                    config::MAX_DEPTH,
-                    None,
-                ));
-            };
-        } else {
-            debug!("Unknown command: {}", command)
         }
     }
 }
 
-pub fn change_position(arguments: &[&str]) -> Position {
-    let mut new_board = chess::Board::default();
-    let mut hash_history: Vec<u64> = vec![];
-
-    let moves_index = arguments
-        .iter()
-        .position(|&r| r == "moves")
-        .unwrap_or(arguments.len());
-
-    if arguments[0] == "fen" {
-        let fen_string = arguments[1..moves_index].join(" ");
-        new_board = chess::Board::from_str(&fen_string).unwrap_or(new_board);
+// Prints the bestmove of a finished or stopped search, errors are reported to
+// the GUI as "info string"
+fn report_outcome(outcome: Result<SearchOutcome, SearchGroupError>, printer: &Printer) {
+    match outcome {
+        Ok(outcome) => {
+            info!("search result: {} - {}\n", outcome.0, outcome.1);
+            printer.bestmove(outcome);
+        }
+        Err(SearchGroupError::NotRunning) => debug!("no search running"),
+        Err(search_error) => printer.print(&format!("info string {search_error}")),
     }
+}
 
-    hash_history.push(new_board.get_hash());
+// Options changed during the last search take effect with the next one
+fn configure_engine(
+    engine: &SearchEngine,
+    options: &EngineOptions,
+) -> Result<(), SearchGroupError> {
+    engine.configure(options.threads, options.hash_table_size())?;
+    engine.set_network(options.active_network())?;
+    #[cfg(feature = "tune")]
+    engine.set_eval_params(options.eval_params.clone())?;
+    Ok(())
+}
 
-    if moves_index >= arguments.len() {
-        return Position {
-            board: new_board,
-            hash_history,
-        };
+fn report_error(result: Result<(), SearchGroupError>, printer: &Printer) {
+    if let Err(search_error) = result {
+        printer.print(&format!("info string {search_error}"));
     }
+}
 
-    for move_str in &arguments[moves_index + 1..] {
-        let parsed_move_result = chess::ChessMove::from_str(*move_str);
-
-        if parsed_move_result.is_ok() {
-            let move_obj = parsed_move_result.unwrap();
+// The moves of "go searchmoves" that are legal in the position, the others
+// are reported and left out. Without any legal one all moves are searched.
+fn legal_search_moves(
+    limits: &SearchLimits,
+    position: &Position,
+    printer: &Printer,
+) -> Vec<ChessMove> {
+    let legal_moves = position.legal_moves();
+    let (root_moves, illegal): (Vec<ChessMove>, Vec<ChessMove>) = limits
+        .searchmoves
+        .iter()
+        .partition(|chess_move| legal_moves.contains(chess_move));
 
-            if new_board.legal(move_obj) {
-                new_board = new_board.make_move_new(move_obj);
-                hash_history.push(new_board.get_hash());
-            }
-        }
+    for chess_move in illegal {
+        let error = UciError::IllegalMove(chess_move.to_string());
+        printer.print(&format!("info string searchmoves: {error}"));
     }
-
-    Position {
-        board: new_board,
-        hash_history,
+    if root_moves.is_empty() && !limits.searchmoves.is_empty() {
+        printer.print("info string no legal searchmoves, searching all moves");
     }
+    root_moves
 }
 
-pub fn collect_user_input() -> String {
-    let mut user_input = String::new();
-    match io::stdin().read_line(&mut user_input) {
-        Ok(_) => {}
-        Err(_) => {}
+pub(crate) fn search_depth(limits: &SearchLimits) -> SearchDepth {
+    let mut depth = limits.depth.unwrap_or(config::MAX_DEPTH);
+
+    if let Some(mate) = limits.mate {
+        let mate_depth = mate.saturating_mul(2).saturating_sub(1);
+        depth = depth.min(mate_depth.min(config::MAX_DEPTH as u32) as SearchDepth);
     }
 
-    user_input.trim().to_string()
+    depth.clamp(1, config::MAX_DEPTH)
 }
 
-fn printing_loop(receiver: PrinterReceiver) {
-    let update_interval = time::Duration::from_millis(10);
-
-    loop {
-        sleep(update_interval);
-
-        let message = receiver.str.try_recv().unwrap_or("".to_string());
-        if message.len() > 0 {
-            println!("{message}");
-        }
-
-        let (score, mut best_move, depth) = receiver.info.try_recv().unwrap_or((
-            0,
-            ChessMove::new(Square::A1, Square::A1, None),
-            0,
-        ));
-
-        if depth > 0 && depth <= config::MAX_DEPTH {
-            print_info(score, best_move, depth);
-        } else if depth == config::MAX_DEPTH + 1 {
-            print_score_only(score);
-            best_move = receiver.bestmove.recv().unwrap_or(best_move);
-            println!("bestmove {best_move}");
-        }
-
-        let termination_signal = receiver.stop.try_recv().unwrap_or(false);
-        if termination_signal {
-            return;
-        }
-    }
+pub fn change_position(arguments: &[&str]) -> Result<Position, UciError> {
+    change_position_variant(arguments, false)
 }
 
-fn print_info(score: i32, best_move: ChessMove, depth: u8) {
-    if score.abs() > config::MATE_THRESHOLD {
-        let mut mate_distance = config::INFINITY - score.abs();
-
-        if score < 0 {
-            mate_distance = -mate_distance;
-        } else {
-            mate_distance += 1
-        }
-        println!("info depth {depth} score mate {mate_distance} pv {best_move}")
-    } else {
-        println!("info depth {depth} score cp {score} pv {best_move}");
-    }
+pub fn change_position_variant(arguments: &[&str], chess960: bool) -> Result<Position, UciError> {
+    let (mut position, moves) = parse_position(arguments)?;
+    position.set_chess960(chess960)?;
+    position.push_moves(&moves)?;
+    Ok(position)
 }
 
-fn print_score_only(score: i32) {
-    if score.abs() > config::MATE_THRESHOLD {
-        let mut mate_distance = config::INFINITY - score.abs();
+// Starts reading the input and lets the engine report the end of its searches
+// on the same channel
+pub(crate) fn spawn_input_reader<R: BufRead + Send + 'static>(
+    input: R,
+    engine: &SearchEngine,
+) -> Receiver<LoopEvent> {
+    let (sender, receiver) = channel();
+    let finished = sender.clone();
+    engine.set_finished_callback(Arc::new(move || {
+        let _ = finished.send(LoopEvent::SearchFinished);
+    }));
+    thread::spawn(move || read_input(input, sender));
+    receiver
+}
 
-        if score < 0 {
-            mate_distance = -mate_distance;
-        } else {
-            mate_distance += 1
+fn read_input<R: BufRead>(input: R, sender: Sender<LoopEvent>) {
+    // Reading the input blocks, so it happens on a dedicated thread and the uci
+    // loop stays responsive during a search. A closed input is treated like
+    // "quit", as there is nobody left to send commands to the engine.
+    for line in input.lines() {
+        match line {
+            Ok(line) => {
+                if sender
+                    .send(LoopEvent::Input(line.trim().to_string()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Err(_) => break,
         }
-        println!("info score mate {mate_distance}")
-    } else {
-        println!("info score cp {score}");
     }
+    let _ = sender.send(LoopEvent::Input(QUIT_COMMAND.to_string()));
 }
@@ -0,0 +1,346 @@
+use chess::{Board, BoardBuilder, ChessMove, Piece, Rank};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::chess960::CastlingRights;
+use crate::position::Position;
+use crate::search::SearchDepth;
+#[cfg(feature = "tree")]
+use crate::tree::TreeFormat;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UciCommand {
+    Uci,
+    Debug(bool),
+    IsReady,
+    SetOption {
+        name: String,
+        value: Option<String>,
+    },
+    Register,
+    UciNewGame,
+    Position {
+        start: Position,
+        moves: Vec<ChessMove>,
+    },
+    Go(SearchLimits),
+    Stop,
+    Quit,
+    // Debug command, records the search tree of the current position
+    #[cfg(feature = "tree")]
+    Tree {
+        depth: SearchDepth,
+        plies: usize,
+        format: TreeFormat,
+    },
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchLimits {
+    pub depth: Option<SearchDepth>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u32>,
+    pub movetime: Option<u64>,
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: Option<u64>,
+    pub binc: Option<u64>,
+    pub movestogo: Option<u32>,
+    pub infinite: bool,
+    pub searchmoves: Vec<ChessMove>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UciError {
+    EmptyCommand,
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    UnexpectedArgument(String),
+    UnsupportedArgument(&'static str),
+    InvalidValue(&'static str, String),
+    InvalidFen(String),
+    InvalidMove(String),
+    IllegalMove(String),
+    UnknownOption(String),
+    InvalidCastlingRights(String),
+}
+
+impl fmt::Display for UciError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UciError::EmptyCommand => write!(f, "empty command"),
+            UciError::UnknownCommand(command) => write!(f, "unknown command: {command}"),
+            UciError::MissingArgument(name) => write!(f, "missing argument: {name}"),
+            UciError::UnexpectedArgument(argument) => {
+                write!(f, "unexpected argument: {argument}")
+            }
+            UciError::UnsupportedArgument(argument) => {
+                write!(f, "unsupported argument: {argument}")
+            }
+            UciError::InvalidValue(name, value) => write!(f, "invalid value for {name}: {value}"),
+            UciError::InvalidFen(fen) => write!(f, "invalid fen: {fen}"),
+            UciError::InvalidMove(chess_move) => write!(f, "invalid move: {chess_move}"),
+            UciError::IllegalMove(chess_move) => write!(f, "illegal move: {chess_move}"),
+            UciError::UnknownOption(name) => write!(f, "unknown option: {name}"),
+            UciError::InvalidCastlingRights(fen) => write!(f, "invalid castling rights: {fen}"),
+        }
+    }
+}
+
+impl FromStr for UciCommand {
+    type Err = UciError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        parse_command(line)
+    }
+}
+
+pub fn parse_command(line: &str) -> Result<UciCommand, UciError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+
+    if tokens.is_empty() {
+        return Err(UciError::EmptyCommand);
+    }
+
+    // The protocol asks engines to skip unknown tokens in front of a command,
+    // so "joho isready" is treated like "isready"
+    let command_index = tokens
+        .iter()
+        .position(|token| is_command(token))
+        .ok_or(UciError::UnknownCommand(tokens[0].to_string()))?;
+    let arguments = &tokens[command_index + 1..];
+
+    match tokens[command_index] {
+        "uci" => Ok(UciCommand::Uci),
+        "debug" => match arguments.first() {
+            Some(&"on") => Ok(UciCommand::Debug(true)),
+            Some(&"off") => Ok(UciCommand::Debug(false)),
+            Some(argument) => Err(UciError::UnexpectedArgument(argument.to_string())),
+            None => Err(UciError::MissingArgument("on or off")),
+        },
+        "isready" => Ok(UciCommand::IsReady),
+        "setoption" => parse_setoption(arguments),
+        // Registration is not required, "register later" and
+        // "register name ... code ..." are both accepted and ignored
+        "register" => Ok(UciCommand::Register),
+        "ucinewgame" => Ok(UciCommand::UciNewGame),
+        "position" => {
+            let (start, moves) = parse_position(arguments)?;
+            Ok(UciCommand::Position { start, moves })
+        }
+        "go" => Ok(UciCommand::Go(parse_go(arguments)?)),
+        "stop" => Ok(UciCommand::Stop),
+        "quit" => Ok(UciCommand::Quit),
+        #[cfg(feature = "tree")]
+        "tree" => parse_tree(arguments),
+        command => Err(UciError::UnknownCommand(command.to_string())),
+    }
+}
+
+fn is_command(token: &str) -> bool {
+    matches!(
+        token,
+        "uci"
+            | "debug"
+            | "isready"
+            | "setoption"
+            | "register"
+            | "ucinewgame"
+            | "position"
+            | "go"
+            | "stop"
+            | "quit"
+    ) || (cfg!(feature = "tree") && token == "tree")
+}
+
+// tree [depth <depth>] [plies <plies>] [dot | json]
+#[cfg(feature = "tree")]
+fn parse_tree(arguments: &[&str]) -> Result<UciCommand, UciError> {
+    let mut depth = 4;
+    let mut plies = 2;
+    let mut format = TreeFormat::Dot;
+    let mut tokens = arguments.iter();
+
+    while let Some(&token) = tokens.next() {
+        match token {
+            "depth" => depth = parse_value("depth", tokens.next())?,
+            "plies" => plies = parse_value("plies", tokens.next())?,
+            "dot" => format = TreeFormat::Dot,
+            "json" => format = TreeFormat::Json,
+            argument => return Err(UciError::UnexpectedArgument(argument.to_string())),
+        }
+    }
+
+    Ok(UciCommand::Tree {
+        depth,
+        plies,
+        format,
+    })
+}
+
+fn parse_setoption(arguments: &[&str]) -> Result<UciCommand, UciError> {
+    // Both the name and the value may contain spaces, e.g.
+    // "setoption name Clear Hash" or "setoption name EvalFile value my net.bin"
+    if arguments.first() != Some(&"name") {
+        return Err(UciError::MissingArgument("name"));
+    }
+
+    let value_index = arguments
+        .iter()
+        .position(|&r| r == "value")
+        .unwrap_or(arguments.len());
+
+    let name = arguments[1..value_index].join(" ");
+    if name.is_empty() {
+        return Err(UciError::MissingArgument("name"));
+    }
+
+    let value = arguments
+        .get(value_index + 1..)
+        .map(|value_tokens| value_tokens.join(" "));
+
+    Ok(UciCommand::SetOption { name, value })
+}
+
+pub fn parse_position(arguments: &[&str]) -> Result<(Position, Vec<ChessMove>), UciError> {
+    let moves_index = arguments
+        .iter()
+        .position(|&r| r == "moves")
+        .unwrap_or(arguments.len());
+
+    let start = match arguments.first() {
+        None => return Err(UciError::MissingArgument("startpos or fen")),
+        Some(&"startpos") => {
+            if moves_index > 1 {
+                return Err(UciError::UnexpectedArgument(arguments[1].to_string()));
+            }
+            Position::default()
+        }
+        Some(&"fen") => parse_fen_position(&arguments[1..moves_index])?,
+        Some(argument) => return Err(UciError::UnexpectedArgument(argument.to_string())),
+    };
+
+    let moves = match arguments.get(moves_index + 1..) {
+        Some(move_strings) => move_strings
+            .iter()
+            .map(|move_str| parse_move(move_str))
+            .collect::<Result<Vec<ChessMove>, UciError>>()?,
+        None => vec![],
+    };
+
+    Ok((start, moves))
+}
+
+// Parsed positions always use the Chess960 representation of the castling
+// rights, so Shredder- and X-FEN are accepted. Position::set_chess960 converts
+// them to standard chess.
+fn parse_fen_position(fen_tokens: &[&str]) -> Result<Position, UciError> {
+    let fen = fen_tokens.join(" ");
+    if fen_tokens.len() < 4 || fen_tokens.len() > 6 {
+        return Err(UciError::InvalidFen(fen));
+    }
+
+    let mut board_tokens = fen_tokens[..4].to_vec();
+    board_tokens[2] = "-";
+    let board = parse_fen(&board_tokens.join(" "))?;
+    let castling = CastlingRights::parse(fen_tokens[2], &board)
+        .ok_or(UciError::InvalidCastlingRights(fen.clone()))?;
+
+    // The move counters are optional, the chess crate ignores them entirely
+    let mut counters = [0, 1];
+    for (counter, token) in counters.iter_mut().zip(fen_tokens.iter().skip(4)) {
+        *counter = token
+            .parse()
+            .map_err(|_| UciError::InvalidFen(fen.clone()))?;
+    }
+
+    Position::with_castling(board, castling, counters[0], counters[1])
+}
+
+pub fn parse_fen(fen: &str) -> Result<Board, UciError> {
+    if fen.is_empty() {
+        return Err(UciError::MissingArgument("fen"));
+    }
+
+    let builder = BoardBuilder::from_str(fen).map_err(|_| UciError::InvalidFen(fen.to_string()))?;
+    if !is_convertible(&builder) {
+        return Err(UciError::InvalidFen(fen.to_string()));
+    }
+
+    Board::try_from(builder).map_err(|_| UciError::InvalidFen(fen.to_string()))
+}
+
+// The chess crate panics when converting a board without kings and its move
+// generator cannot handle pawns on the first or last rank, so both have to be
+// rejected before the conversion
+pub fn is_convertible(builder: &BoardBuilder) -> bool {
+    let mut kings = [0; 2];
+    for square in chess::ALL_SQUARES {
+        match builder[square] {
+            Some((Piece::King, color)) => kings[color.to_index()] += 1,
+            Some((Piece::Pawn, _)) if matches!(square.get_rank(), Rank::First | Rank::Eighth) => {
+                return false
+            }
+            _ => {}
+        }
+    }
+    kings == [1, 1]
+}
+
+pub fn parse_move(move_str: &str) -> Result<ChessMove, UciError> {
+    if move_str.len() != 4 && move_str.len() != 5 {
+        return Err(UciError::InvalidMove(move_str.to_string()));
+    }
+    ChessMove::from_str(move_str).map_err(|_| UciError::InvalidMove(move_str.to_string()))
+}
+
+fn parse_go(arguments: &[&str]) -> Result<SearchLimits, UciError> {
+    let mut limits = SearchLimits::default();
+    let mut tokens = arguments.iter();
+
+    while let Some(&token) = tokens.next() {
+        match token {
+            "depth" => limits.depth = Some(parse_value("depth", tokens.next())?),
+            "nodes" => limits.nodes = Some(parse_value("nodes", tokens.next())?),
+            "mate" => limits.mate = Some(parse_value("mate", tokens.next())?),
+            "movetime" => limits.movetime = Some(parse_value("movetime", tokens.next())?),
+            "wtime" => limits.wtime = Some(parse_time("wtime", tokens.next())?),
+            "btime" => limits.btime = Some(parse_time("btime", tokens.next())?),
+            "winc" => limits.winc = Some(parse_time("winc", tokens.next())?),
+            "binc" => limits.binc = Some(parse_time("binc", tokens.next())?),
+            "movestogo" => limits.movestogo = Some(parse_value("movestogo", tokens.next())?),
+            "infinite" => limits.infinite = true,
+            // There is no "ponderhit" to switch a ponder search to the clock,
+            // and the Ponder option is not offered
+            "ponder" => return Err(UciError::UnsupportedArgument("ponder")),
+            "searchmoves" => {
+                // searchmoves is followed by moves until the next keyword
+                while let Some(move_str) = tokens.clone().next() {
+                    match parse_move(move_str) {
+                        Ok(chess_move) => {
+                            limits.searchmoves.push(chess_move);
+                            tokens.next();
+                        }
+                        Err(_) => break,
+                    }
+                }
+            }
+            argument => return Err(UciError::UnexpectedArgument(argument.to_string())),
+        }
+    }
+
+    Ok(limits)
+}
+
+fn parse_value<T: FromStr>(name: &'static str, value: Option<&&str>) -> Result<T, UciError> {
+    let value = value.ok_or(UciError::MissingArgument(name))?;
+    value
+        .parse()
+        .map_err(|_| UciError::InvalidValue(name, value.to_string()))
+}
+
+fn parse_time(name: &'static str, value: Option<&&str>) -> Result<u64, UciError> {
+    // Some GUIs send negative remaining time when the clock ran out
+    let time: i64 = parse_value(name, value)?;
+    Ok(time.max(0) as u64)
+}
@@ -0,0 +1,485 @@
+use chess::{ChessMove, Color};
+use log::{error, info};
+use std::io::{BufRead, Write};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::options::EngineOptions;
+use crate::position::Position;
+use crate::printer::{attach_logger, mate_distance, set_debug, OutputEvent, Printer};
+use crate::search::{PositionScore, SearchDepth, SearchInfo, SearchOutcome};
+use crate::threading::{SearchEngine, SearchGroupError};
+use crate::time_control::allocate_time;
+use crate::uci::{search_depth, spawn_input_reader, LoopEvent, QUIT_COMMAND};
+use crate::uci_command::{parse_move, parse_position, SearchLimits, UciError};
+
+// Commands of the XBoard protocol (CECP version 2). Commands that only make
+// sense for engines with features this engine does not have are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum XboardCommand {
+    Xboard,
+    Protover(u32),
+    New,
+    Force,
+    Go,
+    PlayOther,
+    MoveNow,
+    Level {
+        moves_per_session: u32,
+        base_ms: u64,
+        increment_ms: u64,
+    },
+    SearchTime(u64),
+    SearchDepth(SearchDepth),
+    Time(u64),
+    OpponentTime(u64),
+    UserMove(ChessMove),
+    SetBoard(Box<Position>),
+    Undo,
+    Remove,
+    Post(bool),
+    Ping(String),
+    Result,
+    Memory(String),
+    Cores(String),
+    Ignored,
+    Quit,
+}
+
+// The engine always plays the side given by engine_side, after "force" it
+// plays neither side and only keeps track of the moves
+struct XboardState {
+    position: Position,
+    engine_side: Option<Color>,
+    level: (u32, u64, u64),
+    search_time_ms: Option<u64>,
+    max_depth: Option<SearchDepth>,
+    engine_clock_ms: Option<u64>,
+    post: bool,
+}
+
+impl XboardState {
+    fn new() -> XboardState {
+        XboardState {
+            position: Position::default(),
+            engine_side: Some(Color::Black),
+            level: DEFAULT_LEVEL,
+            search_time_ms: None,
+            max_depth: None,
+            engine_clock_ms: None,
+            post: false,
+        }
+    }
+
+    fn search_limits(&self) -> SearchLimits {
+        let (moves_per_session, base_ms, increment_ms) = self.level;
+        let clock_ms = self.engine_clock_ms.unwrap_or(base_ms);
+
+        let mut limits = SearchLimits {
+            depth: self.max_depth,
+            movetime: self.search_time_ms,
+            wtime: Some(clock_ms),
+            btime: Some(clock_ms),
+            winc: Some(increment_ms),
+            binc: Some(increment_ms),
+            ..SearchLimits::default()
+        };
+
+        // Both sides have played one move less than the current move number
+        if moves_per_session > 0 {
+            let moves_played = self.position.fullmove_number().saturating_sub(1);
+            limits.movestogo = Some(moves_per_session - moves_played % moves_per_session);
+        }
+        limits
+    }
+}
+
+// The search itself is owned by the SearchEngine, this is only what is needed
+// for the thinking output
+struct RunningSearch {
+    infos: Receiver<OutputEvent>,
+    started: Instant,
+}
+
+// 40 moves in 5 minutes, the default of xboard
+const DEFAULT_LEVEL: (u32, u64, u64) = (40, 300_000, 0);
+const MATE_SCORE: PositionScore = 100000;
+const THINKING_INTERVAL: Duration = Duration::from_millis(5);
+
+pub fn xboard_loop<R, W>(input: R, output: W)
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    info!("xboard mode started\n");
+
+    let printer = Printer::start(output);
+    attach_logger(&printer);
+    set_debug(false);
+
+    let mut options = EngineOptions::default();
+    let engine = SearchEngine::new(options.threads, options.hash_table_size());
+    let mut state = XboardState::new();
+    let events = spawn_input_reader(input, &engine);
+    let mut search: Option<RunningSearch> = None;
+
+    loop {
+        // The thinking output is read from the search on every tick
+        let event = match search {
+            Some(ref running) => match events.recv_timeout(THINKING_INTERVAL) {
+                Err(RecvTimeoutError::Timeout) => {
+                    print_thinking(running, &state, &printer);
+                    continue;
+                }
+                event => event.ok(),
+            },
+            None => events.recv().ok(),
+        };
+        let input_line = match event {
+            Some(LoopEvent::Input(line)) => line,
+            Some(LoopEvent::SearchFinished) => {
+                if let (Some(running), true) = (&search, engine.is_finished()) {
+                    print_thinking(running, &state, &printer);
+                    let outcome = engine.wait();
+                    play_outcome(outcome, search.take(), &mut state, &printer);
+                }
+                continue;
+            }
+            None => QUIT_COMMAND.to_string(),
+        };
+        info!("{}\n", input_line);
+
+        let command = match parse_xboard_command(&input_line) {
+            Ok(command) => command,
+            Err(UciError::EmptyCommand) => continue,
+            Err(UciError::InvalidMove(move_str)) | Err(UciError::IllegalMove(move_str)) => {
+                printer.print(&format!("Illegal move: {move_str}"));
+                continue;
+            }
+            Err(UciError::InvalidFen(_)) | Err(UciError::InvalidCastlingRights(_)) => {
+                printer.print("tellusererror Illegal position");
+                continue;
+            }
+            Err(parse_error) => {
+                printer.print(&format!("Error ({parse_error}): {input_line}"));
+                continue;
+            }
+        };
+
+        match command {
+            XboardCommand::Xboard | XboardCommand::Ignored => {}
+            XboardCommand::Protover(_) => {
+                printer.print(&format!(
+                    "feature myname=\"{}\" usermove=1 setboard=1 ping=1 colors=0 \
+                     sigint=0 sigterm=0 memory=1 smp=1 done=1",
+                    config::ENGINE_NAME
+                ));
+            }
+            XboardCommand::New => {
+                cancel_search(&engine, search.take());
+                if let Err(search_error) = engine.new_game() {
+                    printer.print(&format!("tellusererror {search_error}"));
+                }
+                state = XboardState {
+                    post: state.post,
+                    ..XboardState::new()
+                };
+            }
+            XboardCommand::Force => {
+                cancel_search(&engine, search.take());
+                state.engine_side = None;
+            }
+            XboardCommand::Go => {
+                state.engine_side = Some(state.position.board.side_to_move());
+            }
+            XboardCommand::PlayOther => {
+                state.engine_side = Some(!state.position.board.side_to_move());
+            }
+            XboardCommand::MoveNow => {
+                if search.is_some() {
+                    let outcome = engine.stop();
+                    play_outcome(outcome, search.take(), &mut state, &printer);
+                }
+            }
+            XboardCommand::Level {
+                moves_per_session,
+                base_ms,
+                increment_ms,
+            } => {
+                state.level = (moves_per_session, base_ms, increment_ms);
+                state.search_time_ms = None;
+            }
+            XboardCommand::SearchTime(time_ms) => state.search_time_ms = Some(time_ms),
+            XboardCommand::SearchDepth(depth) => state.max_depth = Some(depth),
+            XboardCommand::Time(time_ms) => state.engine_clock_ms = Some(time_ms),
+            XboardCommand::OpponentTime(_) => {}
+            XboardCommand::UserMove(chess_move) => {
+                if search.is_some() {
+                    printer.print(&format!("Error (engine is thinking): {input_line}"));
+                } else if state.position.push(chess_move).is_err() {
+                    printer.print(&format!("Illegal move: {chess_move}"));
+                } else if let Some(result) = game_result(&state.position) {
+                    printer.print(result);
+                }
+            }
+            XboardCommand::SetBoard(mut position) => {
+                cancel_search(&engine, search.take());
+                match position.set_chess960(false) {
+                    Ok(_) => state.position = *position,
+                    Err(_) => printer.print("tellusererror Illegal position"),
+                }
+            }
+            XboardCommand::Undo => {
+                cancel_search(&engine, search.take());
+                state.position.pop();
+            }
+            XboardCommand::Remove => {
+                cancel_search(&engine, search.take());
+                state.position.pop();
+                state.position.pop();
+            }
+            XboardCommand::Post(enabled) => state.post = enabled,
+            XboardCommand::Ping(token) => printer.print(&format!("pong {token}")),
+            XboardCommand::Result => {
+                cancel_search(&engine, search.take());
+                state.engine_side = None;
+            }
+            XboardCommand::Memory(megabytes) => {
+                if let Err(option_error) = options.set_option("Hash", Some(&megabytes)) {
+                    printer.print(&format!("Error ({option_error}): {input_line}"));
+                }
+            }
+            XboardCommand::Cores(cores) => {
+                if let Err(option_error) = options.set_option("Threads", Some(&cores)) {
+                    printer.print(&format!("Error ({option_error}): {input_line}"));
+                }
+            }
+            XboardCommand::Quit => {
+                cancel_search(&engine, search.take());
+                printer.stop();
+                engine.shutdown();
+
+                info!("shutting down");
+                return;
+            }
+        }
+
+        // Unlike in uci the engine decides itself when to think, which is
+        // whenever it is on move and the game is not over yet
+        let engine_to_move = state.engine_side == Some(state.position.board.side_to_move());
+        if search.is_none() && engine_to_move && game_result(&state.position).is_none() {
+            match start_search(&state, &options, &engine) {
+                Ok(running) => search = Some(running),
+                Err(search_error) => {
+                    // Without force mode every following command would retry
+                    printer.print(&format!("tellusererror {search_error}"));
+                    state.engine_side = None;
+                }
+            }
+        }
+    }
+}
+
+fn start_search(
+    state: &XboardState,
+    options: &EngineOptions,
+    engine: &SearchEngine,
+) -> Result<RunningSearch, SearchGroupError> {
+    let limits = state.search_limits();
+    let (info_sender, infos) = channel();
+
+    engine.configure(options.threads, options.hash_table_size())?;
+    engine.start(
+        &state.position,
+        info_sender,
+        search_depth(&limits),
+        allocate_time(&limits, state.position.board.side_to_move())
+            .and_then(|time| Instant::now().checked_add(time)),
+        None,
+        &[],
+    )?;
+
+    Ok(RunningSearch {
+        infos,
+        started: Instant::now(),
+    })
+}
+
+// Plays the best move of a finished or stopped search
+fn play_outcome(
+    outcome: Result<SearchOutcome, SearchGroupError>,
+    search: Option<RunningSearch>,
+    state: &mut XboardState,
+    printer: &Printer,
+) {
+    match outcome {
+        Ok((_, best_move)) => {
+            if let Some(running) = search {
+                print_thinking(&running, state, printer);
+            }
+            play_engine_move(best_move, state, printer);
+        }
+        Err(search_error) => printer.print(&format!("tellusererror {search_error}")),
+    }
+}
+
+// Stops the search without playing its move, e.g. after "force" or "undo"
+fn cancel_search(engine: &SearchEngine, search: Option<RunningSearch>) {
+    if search.is_some() {
+        if let Err(search_error) = engine.stop() {
+            error!("cancel; {search_error}")
+        }
+    }
+}
+
+fn play_engine_move(chess_move: ChessMove, state: &mut XboardState, printer: &Printer) {
+    if state.position.push(chess_move).is_err() {
+        error!("search returned the illegal move {chess_move}");
+        return;
+    }
+
+    printer.print(&format!("move {chess_move}"));
+    if let Some(result) = game_result(&state.position) {
+        printer.print(result);
+    }
+}
+
+fn game_result(position: &Position) -> Option<&'static str> {
+    if !position.legal_moves().is_empty() {
+        return None;
+    }
+
+    let in_check = *position.board.checkers() != chess::EMPTY;
+    match (in_check, position.board.side_to_move()) {
+        (true, Color::Black) => Some("1-0 {White mates}"),
+        (true, Color::White) => Some("0-1 {Black mates}"),
+        (false, _) => Some("1/2-1/2 {Stalemate}"),
+    }
+}
+
+fn print_thinking(running: &RunningSearch, state: &XboardState, printer: &Printer) {
+    while let Ok(event) = running.infos.try_recv() {
+        if let OutputEvent::Info(search_info) = event {
+            if state.post {
+                printer.print(&format_thinking(search_info, running.started));
+            }
+        }
+    }
+}
+
+// Thinking output is "ply score time nodes pv", the time in centiseconds. The
+// nodes are those of the main search thread, which sends the infos.
+fn format_thinking((score, best_move, depth, nodes): SearchInfo, started: Instant) -> String {
+    let centiseconds = started.elapsed().as_millis() / 10;
+    format!(
+        "{depth} {} {centiseconds} {nodes} {best_move}",
+        xboard_score(score)
+    )
+}
+
+// Mate scores are reported as 100000 + the number of moves to mate, which is
+// the convention most GUIs understand
+pub fn xboard_score(score: PositionScore) -> PositionScore {
+    match mate_distance(score) {
+        Some(distance) if distance > 0 => MATE_SCORE + distance,
+        Some(distance) => -MATE_SCORE + distance,
+        None => score,
+    }
+}
+
+pub fn parse_xboard_command(line: &str) -> Result<XboardCommand, UciError> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    let (&command, arguments) = tokens.split_first().ok_or(UciError::EmptyCommand)?;
+
+    match command {
+        "xboard" => Ok(XboardCommand::Xboard),
+        "protover" => Ok(XboardCommand::Protover(parse_argument(
+            "protover", arguments,
+        )?)),
+        "new" => Ok(XboardCommand::New),
+        "force" => Ok(XboardCommand::Force),
+        "go" => Ok(XboardCommand::Go),
+        "playother" => Ok(XboardCommand::PlayOther),
+        "?" => Ok(XboardCommand::MoveNow),
+        "level" => parse_level(arguments),
+        "st" => {
+            let seconds: f64 = parse_argument("st", arguments)?;
+            Ok(XboardCommand::SearchTime(
+                (seconds.max(0.0) * 1000.0) as u64,
+            ))
+        }
+        "sd" => Ok(XboardCommand::SearchDepth(parse_argument("sd", arguments)?)),
+        // Both clocks are given in centiseconds and may be negative
+        "time" => Ok(XboardCommand::Time(parse_centiseconds("time", arguments)?)),
+        "otim" => Ok(XboardCommand::OpponentTime(parse_centiseconds(
+            "otim", arguments,
+        )?)),
+        "usermove" => {
+            let move_str = arguments.first().ok_or(UciError::MissingArgument("move"))?;
+            Ok(XboardCommand::UserMove(parse_move(move_str)?))
+        }
+        "setboard" => {
+            let fen_arguments: Vec<&str> = ["fen"].iter().chain(arguments).copied().collect();
+            let (position, _) = parse_position(&fen_arguments)?;
+            Ok(XboardCommand::SetBoard(Box::new(position)))
+        }
+        "undo" => Ok(XboardCommand::Undo),
+        "remove" => Ok(XboardCommand::Remove),
+        "post" => Ok(XboardCommand::Post(true)),
+        "nopost" => Ok(XboardCommand::Post(false)),
+        "ping" => Ok(XboardCommand::Ping(arguments.join(" "))),
+        "result" => Ok(XboardCommand::Result),
+        "memory" => Ok(XboardCommand::Memory(arguments.join(" "))),
+        "cores" => Ok(XboardCommand::Cores(arguments.join(" "))),
+        "quit" => Ok(XboardCommand::Quit),
+        "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating"
+        | "ics" | "draw" | "hint" | "bk" | "variant" | "white" | "black" => {
+            Ok(XboardCommand::Ignored)
+        }
+        // Engines that did not enable usermove=1 receive bare moves
+        _ => match parse_move(command) {
+            Ok(chess_move) => Ok(XboardCommand::UserMove(chess_move)),
+            Err(_) => Err(UciError::UnknownCommand(command.to_string())),
+        },
+    }
+}
+
+// "level 40 5 0" or "level 0 2:30 1.5", the base time is in minutes or
+// minutes:seconds and the increment in seconds
+fn parse_level(arguments: &[&str]) -> Result<XboardCommand, UciError> {
+    if arguments.len() != 3 {
+        return Err(UciError::MissingArgument("level"));
+    }
+
+    let moves_per_session = parse_argument("level", &arguments[..1])?;
+    let base_seconds = match arguments[1].split_once(':') {
+        Some((minutes, seconds)) => {
+            parse_argument::<u64>("level", &[minutes])? * 60
+                + parse_argument::<u64>("level", &[seconds])?
+        }
+        None => parse_argument::<u64>("level", &arguments[1..2])? * 60,
+    };
+    let increment_seconds: f64 = parse_argument("level", &arguments[2..])?;
+
+    Ok(XboardCommand::Level {
+        moves_per_session,
+        base_ms: base_seconds * 1000,
+        increment_ms: (increment_seconds.max(0.0) * 1000.0) as u64,
+    })
+}
+
+fn parse_argument<T: std::str::FromStr>(
+    name: &'static str,
+    arguments: &[&str],
+) -> Result<T, UciError> {
+    let value = arguments.first().ok_or(UciError::MissingArgument(name))?;
+    value
+        .parse()
+        .map_err(|_| UciError::InvalidValue(name, value.to_string()))
+}
+
+fn parse_centiseconds(name: &'static str, arguments: &[&str]) -> Result<u64, UciError> {
+    let centiseconds: i64 = parse_argument(name, arguments)?;
+    Ok(centiseconds.max(0) as u64 * 10)
+}
@@ -1,8 +1,10 @@
-use chess;
-use rust_chess;
 use std::str::FromStr;
 
-use rust_chess::test_utils::setup_test_context;
+use rust_chess::position::Position;
+use rust_chess::test_utils::{setup_test_context, TestEngine};
+use rust_chess::threading::{SearchEngine, SearchGroupError, ThreadPool};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
 #[cfg(test)]
 mod tests {
@@ -51,7 +53,7 @@ mod tests {
             "fen R5k1/8/6pp/5p2/P4P2/r3P3/5KPP/8 b - - 1 43 moves g8g7 a8a7 g7g8"
                 .split(" ")
                 .collect();
-        let position = rust_chess::uci::change_position(&command[0..]);
+        let position = rust_chess::uci::change_position(&command[0..]).expect("Invalid position");
         let fen = position.board.to_string();
         let max_depth = 6;
         let mut context = setup_test_context(position.board);
@@ -70,7 +72,7 @@ mod tests {
             "fen R5k1/8/6pp/5p2/P4P2/r3P3/5KPP/8 b - - 1 43 moves g8g7 a8a7 g7g8 a7a8"
                 .split(" ")
                 .collect();
-        let position = rust_chess::uci::change_position(&command[0..]);
+        let position = rust_chess::uci::change_position(&command[0..]).expect("Invalid position");
         let fen = position.board.to_string();
         let max_depth = 6;
         let mut context = setup_test_context(position.board);
@@ -217,4 +219,481 @@ mod tests {
     // }
 
     // Mate in 12: 8/3P3k/n2K3p/2p3n1/1b4N1/2p1p1P1/8/3B4 w - - 0 1
+
+    fn run_uci_transcript(commands: &[&str]) -> Vec<String> {
+        use std::io::{BufRead, BufReader, Write};
+        use std::process::{Command, Stdio};
+
+        let mut engine = Command::new(env!("CARGO_BIN_EXE_rust_chess"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Engine did not start");
+
+        let mut stdin = engine.stdin.take().expect("No stdin");
+        for command in commands {
+            writeln!(stdin, "{command}").expect("Engine closed stdin");
+        }
+
+        // Closing stdin quits the engine, so the transcript is read until the
+        // search of the last "go" command reported its best move
+        let stdout = engine.stdout.take().expect("No stdout");
+        let mut lines = vec![];
+        for line in BufReader::new(stdout).lines() {
+            let line = line.expect("Invalid output");
+            let is_bestmove = line.starts_with("bestmove");
+            lines.push(line);
+            if is_bestmove {
+                break;
+            }
+        }
+        drop(stdin);
+
+        engine.wait().expect("Engine did not exit");
+        lines
+    }
+
+    #[test]
+    fn uci_handshake_transcript() {
+        let output = run_uci_transcript(&[
+            "uci",
+            "debug off",
+            "setoption name Hash value 8",
+            "setoption name Threads value 2",
+            "setoption name Unknown value 1",
+            "register later",
+            "isready",
+            "ucinewgame",
+            "position startpos moves e2e4",
+            "go depth 3",
+        ]);
+
+        assert!(output[0].starts_with("id name rust_chess"));
+        assert!(output[1].starts_with("id author "));
+
+        let uciok = output
+            .iter()
+            .position(|line| line == "uciok")
+            .expect("No uciok");
+        assert!(output[2..uciok]
+            .iter()
+            .all(|line| line.starts_with("option name ")));
+        assert!(output[2..uciok]
+            .iter()
+            .any(|line| line.starts_with("option name Hash type spin")));
+        assert!(output[2..uciok]
+            .iter()
+            .any(|line| line.starts_with("option name Threads type spin")));
+
+        assert_eq!(output[uciok + 1], "info string unknown option: Unknown");
+        assert_eq!(output[uciok + 2], "readyok");
+        assert!(output[uciok + 3].starts_with("info depth 1 "));
+        assert!(output.last().expect("No output").starts_with("bestmove "));
+    }
+
+    #[test]
+    fn uci_loop_exact_transcript() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("isready");
+        engine.send("position startpos moves e2e4 e2e4");
+        engine.send("go depth 2 nodes");
+        engine.send("isready");
+
+        assert_eq!(engine.read_line(timeout).as_deref(), Some("readyok"));
+        assert_eq!(
+            engine.read_line(timeout).as_deref(),
+            Some("info string illegal move: e2e4")
+        );
+        assert_eq!(
+            engine.read_line(timeout).as_deref(),
+            Some("info string missing argument: nodes")
+        );
+        assert_eq!(engine.read_line(timeout).as_deref(), Some("readyok"));
+
+        engine.send("setoption name Hash value 1");
+        engine.send("setoption name Threads value 1");
+        engine.send("position fen 7k/5Q2/6K1/8/8/8/8/8 w - - 0 1");
+        engine.send("go depth 2");
+
+        assert_eq!(
+            engine.read_until("bestmove", timeout),
+            vec![
+                "info depth 1 score mate 1 pv f7g7",
+                "info depth 2 score mate 1 pv f7g7",
+                "info score mate 1",
+                "bestmove f7g7",
+            ]
+        );
+        engine.close();
+    }
+
+    #[cfg(feature = "tree")]
+    #[test]
+    fn uci_loop_tree_runs_on_the_engine() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("setoption name Threads value 2");
+        engine.send("position startpos moves e2e4");
+        engine.send("tree depth 3 plies 1 dot");
+
+        let output = engine.read_until("bestmove", timeout);
+        let graph = output
+            .iter()
+            .position(|line| line == "digraph search {")
+            .expect("No tree printed");
+        assert!(output[..graph].iter().all(|line| line.starts_with("info")));
+        assert!(output.last().expect("No output").starts_with("bestmove "));
+
+        // A deep tree search is stopped like any other search
+        engine.send("tree depth 40 plies 1 json");
+        engine.send("isready");
+        assert_eq!(
+            engine
+                .read_until("readyok", timeout)
+                .last()
+                .map(String::as_str),
+            Some("readyok")
+        );
+        engine.send("stop");
+        let output = engine.read_until("bestmove", timeout);
+        assert!(output
+            .iter()
+            .any(|line| line.starts_with("{\"move\":null,")));
+        assert!(output.last().expect("No output").starts_with("bestmove "));
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_go_searchmoves() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("position fen 7k/5Q2/6K1/8/8/8/8/8 w - - 0 1");
+        engine.send("go depth 2 searchmoves f7f8 e7e5");
+
+        let output = engine.read_until("bestmove", timeout);
+        assert_eq!(output[0], "info string searchmoves: illegal move: e7e5");
+        assert_eq!(output.last().map(String::as_str), Some("bestmove f7f8"));
+
+        engine.send("go depth 2 searchmoves e7e5");
+
+        let output = engine.read_until("bestmove", timeout);
+        assert_eq!(
+            output[1],
+            "info string no legal searchmoves, searching all moves"
+        );
+        assert_eq!(output.last().map(String::as_str), Some("bestmove f7g7"));
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_missing_network_reported_once() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("setoption name UseNNUE value true");
+        engine.send("setoption name UseNNUE value true");
+        engine.send("position fen 7k/5Q2/6K1/8/8/8/8/8 w - - 0 1");
+        engine.send("go depth 2");
+
+        let output = engine.read_until("bestmove", timeout);
+        let reports = output
+            .iter()
+            .filter(|line| line.contains("UseNNUE without an EvalFile"))
+            .count();
+        assert_eq!(reports, 1);
+        assert_eq!(
+            output[0],
+            "info string UseNNUE without an EvalFile, using the classical evaluation"
+        );
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_bestmove_after_movetime() {
+        let movetime = Duration::from_millis(300);
+        let mut engine = TestEngine::start();
+
+        engine.send("setoption name Hash value 1");
+        engine.send("position startpos");
+        let start = Instant::now();
+        engine.send(&format!("go movetime {}", movetime.as_millis()));
+
+        let output = engine.read_until("bestmove", Duration::from_secs(10));
+        let elapsed = start.elapsed();
+
+        assert!(output.last().expect("No output").starts_with("bestmove "));
+        assert!(elapsed >= movetime);
+        assert!(elapsed < movetime + Duration::from_secs(5));
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_isready_while_searching() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("setoption name Hash value 1");
+        engine.send("go infinite");
+        engine.send("isready");
+
+        let output = engine.read_until("readyok", timeout);
+        assert_eq!(output.last().map(String::as_str), Some("readyok"));
+
+        engine.send("stop");
+        let output = engine.read_until("bestmove", timeout);
+        assert!(output.last().expect("No output").starts_with("bestmove "));
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_stop_right_after_go() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        for _ in 0..10 {
+            engine.send("position startpos");
+            engine.send("go infinite");
+            engine.send("stop");
+
+            // Not even a stopped search may claim that the engine is mated
+            let output = engine.read_until("bestmove", timeout);
+            assert!(output.last().expect("No output").starts_with("bestmove "));
+            assert!(output.iter().all(|line| !line.contains("score mate 0")));
+        }
+        engine.close();
+    }
+
+    #[test]
+    fn uci_loop_no_legal_moves() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("position fen 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1");
+        engine.send("go depth 5");
+        assert_eq!(
+            engine.read_until("bestmove", timeout),
+            vec!["info score mate 0", "bestmove 0000"]
+        );
+
+        engine.send("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        engine.send("go wtime 1000 btime 1000");
+        assert_eq!(
+            engine.read_until("bestmove", timeout),
+            vec!["info score cp 0", "bestmove 0000"]
+        );
+        engine.close();
+    }
+
+    #[test]
+    fn xboard_transcript() {
+        let timeout = Duration::from_secs(5);
+        let mut engine = TestEngine::start();
+
+        engine.send("xboard");
+        engine.send("protover 2");
+        assert!(engine.read_line(timeout).is_some_and(|line| line
+            .starts_with("feature myname=\"rust_chess")
+            && line.ends_with("done=1")));
+
+        // In force mode the engine only keeps track of the moves
+        engine.send("new");
+        engine.send("force");
+        engine.send("usermove e2e4");
+        engine.send("usermove e2e4");
+        engine.send("undo");
+        engine.send("ping 1");
+        assert_eq!(
+            engine.read_line(timeout).as_deref(),
+            Some("Illegal move: e2e4")
+        );
+        assert_eq!(engine.read_line(timeout).as_deref(), Some("pong 1"));
+
+        engine.send("memory 1");
+        engine.send("cores 1");
+        engine.send("setboard 7k/5Q2/6K1/8/8/8/8/8 w - - 0 1");
+        engine.send("sd 2");
+        engine.send("level 40 5 0");
+        engine.send("post");
+        engine.send("go");
+
+        // Thinking output is "ply score time nodes pv", the time and the
+        // nodes vary
+        let thinking: Vec<String> = engine
+            .read_until("move", timeout)
+            .iter()
+            .map(|line| {
+                let fields: Vec<&str> = line.split(' ').collect();
+                match fields.len() {
+                    5 => {
+                        let nodes: u64 = fields[3].parse().expect("Invalid node count");
+                        assert!(nodes > 0);
+                        format!("{} {} {}", fields[0], fields[1], fields[4])
+                    }
+                    _ => line.clone(),
+                }
+            })
+            .collect();
+        assert_eq!(
+            thinking,
+            vec!["1 100001 f7g7", "2 100001 f7g7", "move f7g7"]
+        );
+        assert_eq!(
+            engine.read_line(timeout).as_deref(),
+            Some("1-0 {White mates}")
+        );
+
+        // After "new" the engine plays black and answers the move of white
+        engine.send("new");
+        engine.send("nopost");
+        engine.send("sd 1");
+        engine.send("usermove e2e4");
+        assert!(engine
+            .read_line(timeout)
+            .is_some_and(|line| line.starts_with("move ")));
+        engine.close();
+    }
+
+    #[test]
+    fn search_group_stops_promptly() {
+        let board = chess::Board::from_str(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        )
+        .expect("Invalid position");
+        let (info_sender, _info_receiver) = mpsc::channel();
+        let mut pool = ThreadPool::new(4, 1 << 16);
+
+        let group = pool
+            .start(
+                &Position::new(board),
+                info_sender,
+                rust_chess::config::MAX_DEPTH,
+                None,
+                None,
+                &[],
+            )
+            .expect("Valid search parameters");
+        std::thread::sleep(Duration::from_millis(500));
+
+        let stopping = Instant::now();
+        assert!(group.stop().is_ok());
+        assert!(stopping.elapsed() < Duration::from_millis(200));
+    }
+
+    #[test]
+    fn search_group_respects_deadline_and_node_limit() {
+        let board = chess::Board::default();
+        let (info_sender, _info_receiver) = mpsc::channel();
+        let mut pool = ThreadPool::new(2, 1 << 16);
+
+        let started = Instant::now();
+        let group = pool
+            .start(
+                &Position::new(board),
+                info_sender.clone(),
+                rust_chess::config::MAX_DEPTH,
+                Some(started + Duration::from_millis(200)),
+                None,
+                &[],
+            )
+            .expect("Valid search parameters");
+        assert!(group.await_principal().is_ok());
+        assert!(started.elapsed() < Duration::from_millis(500));
+
+        pool.resize(1);
+        let group = pool
+            .start(
+                &Position::new(board),
+                info_sender,
+                rust_chess::config::MAX_DEPTH,
+                None,
+                Some(10_000),
+                &[],
+            )
+            .expect("Valid search parameters");
+        assert!(group.await_principal().is_ok());
+    }
+
+    #[test]
+    fn thread_pool_runs_consecutive_searches() {
+        let mut pool = ThreadPool::new(2, 1 << 16);
+        let (info_sender, _info_receiver) = mpsc::channel();
+        let mut position = Position::default();
+
+        for size in [2, 4, 1] {
+            pool.resize(size);
+            assert_eq!(pool.size(), size as usize);
+
+            let group = pool
+                .start(&position, info_sender.clone(), 4, None, None, &[])
+                .expect("Valid search parameters");
+            let (_, best_move) = group.await_principal().expect("Search failed");
+            position.push(best_move).expect("Illegal best move");
+        }
+
+        pool.new_game();
+        let group = pool
+            .start(
+                &position,
+                info_sender,
+                rust_chess::config::MAX_DEPTH,
+                None,
+                None,
+                &[],
+            )
+            .expect("Valid search parameters");
+        assert!(!group.is_finished());
+        assert!(group.stop().is_ok());
+        pool.shutdown();
+    }
+
+    #[test]
+    fn search_engine_reports_typed_errors() {
+        let engine = std::sync::Arc::new(SearchEngine::new(2, 1 << 16));
+        let (info_sender, _info_receiver) = mpsc::channel();
+        let position = Position::default();
+        let max_depth = rust_chess::config::MAX_DEPTH;
+
+        assert_eq!(engine.stop(), Err(SearchGroupError::NotRunning));
+        assert!(matches!(
+            engine.start(&position, info_sender.clone(), 0, None, None, &[]),
+            Err(SearchGroupError::InvalidParameters(_))
+        ));
+
+        let mated = Position::new(
+            chess::Board::from_str("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").expect("Invalid position"),
+        );
+        assert_eq!(
+            engine.start(&mated, info_sender.clone(), 4, None, None, &[]),
+            Err(SearchGroupError::NoLegalMoves)
+        );
+
+        engine
+            .start(&position, info_sender.clone(), max_depth, None, None, &[])
+            .expect("Search did not start");
+        assert_eq!(
+            engine.start(&position, info_sender, max_depth, None, None, &[]),
+            Err(SearchGroupError::AlreadyRunning)
+        );
+        assert_eq!(
+            engine.configure(1, 1 << 16),
+            Err(SearchGroupError::AlreadyRunning)
+        );
+
+        // The handle is shared, one thread waits while another one stops
+        let stopper = {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                engine.stop()
+            })
+        };
+        let waited = engine.wait();
+        let stopped = stopper.join().expect("Stopping thread panicked");
+        assert!(waited.is_ok() != stopped.is_ok());
+        assert!(!engine.is_running());
+    }
 }
@@ -1,8 +1,13 @@
-use chess;
-use rust_chess;
+use chess::Color;
 use std::str::FromStr;
+use std::time::Duration;
 
+use rust_chess::position::Position;
 use rust_chess::test_utils::setup_test_context;
+use rust_chess::threading::{select_best_result, AgentResult};
+use rust_chess::time_control::allocate_time;
+use rust_chess::uci_command::{parse_command, UciCommand, UciError};
+use rust_chess::xboard::{parse_xboard_command, XboardCommand};
 
 #[cfg(test)]
 mod tests {
@@ -40,7 +45,7 @@ mod tests {
             .split(" ")
             .collect();
 
-        let position = rust_chess::uci::change_position(&command[0..]);
+        let position = rust_chess::uci::change_position(&command[0..]).expect("Invalid position");
 
         // Board struct does not track the move number, so there is no halfmove of fullmove clock in the struct*
         let new_board = position.board;
@@ -55,7 +60,7 @@ mod tests {
 
         let extension = rust_chess::search::extend_check(&board, 0);
 
-        assert_eq!(extension, true);
+        assert!(extension);
     }
 
     #[test]
@@ -65,7 +70,7 @@ mod tests {
 
         let extension = rust_chess::search::extend_check(&board, 0);
 
-        assert_eq!(extension, false);
+        assert!(!extension);
     }
 
     #[test]
@@ -75,6 +80,991 @@ mod tests {
 
         let extension = rust_chess::search::extend_check(&board, 3);
 
-        assert_eq!(extension, false);
+        assert!(!extension);
+    }
+
+    #[test]
+    fn format_mate_scores() {
+        let infinity = rust_chess::config::INFINITY;
+
+        assert_eq!(rust_chess::printer::format_score(35), "cp 35");
+        assert_eq!(rust_chess::printer::format_score(infinity - 2), "mate 3");
+        assert_eq!(rust_chess::printer::format_score(-infinity + 1), "mate -1");
+    }
+
+    #[test]
+    fn uci_parse_go() {
+        let command = parse_command("go wtime 1000 btime -20 winc 10 movestogo 5 depth 7");
+
+        let limits = match command {
+            Ok(UciCommand::Go(limits)) => limits,
+            _ => panic!("expected go command"),
+        };
+        assert_eq!(limits.depth, Some(7));
+        assert_eq!(limits.wtime, Some(1000));
+        assert_eq!(limits.btime, Some(0));
+        assert_eq!(limits.winc, Some(10));
+        assert_eq!(limits.movestogo, Some(5));
+    }
+
+    #[test]
+    fn time_allocation() {
+        let allocate = |command: &str, side_to_move| match parse_command(command) {
+            Ok(UciCommand::Go(limits)) => allocate_time(&limits, side_to_move),
+            _ => panic!("expected go command"),
+        };
+
+        assert_eq!(
+            allocate("go wtime 3000 btime 40 winc 400", Color::White),
+            Some(Duration::from_millis(400))
+        );
+        assert_eq!(
+            allocate("go wtime 3000 btime 40 winc 400", Color::Black),
+            Some(Duration::from_millis(0))
+        );
+        assert_eq!(
+            allocate("go wtime 3000 movestogo 2", Color::White),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            allocate("go movetime 500 wtime 100", Color::White),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(allocate("go infinite wtime 3000", Color::White), None);
+        assert_eq!(allocate("go depth 5", Color::White), None);
+    }
+
+    #[test]
+    fn uci_skip_leading_unknown_tokens() {
+        assert_eq!(parse_command("joho isready"), Ok(UciCommand::IsReady));
+    }
+
+    #[test]
+    fn uci_reject_malformed_commands() {
+        assert_eq!(parse_command(""), Err(UciError::EmptyCommand));
+        assert_eq!(
+            parse_command("position"),
+            Err(UciError::MissingArgument("startpos or fen"))
+        );
+        assert_eq!(
+            parse_command("go depth"),
+            Err(UciError::MissingArgument("depth"))
+        );
+        assert_eq!(
+            parse_command("go depth deep"),
+            Err(UciError::InvalidValue("depth", "deep".to_string()))
+        );
+        assert!(matches!(
+            parse_command("position fen 8/8/8/8/8/8/8/8 w - - 0 1"),
+            Err(UciError::InvalidFen(_))
+        ));
+        assert!(matches!(
+            parse_command("position startpos moves e2e4 e7"),
+            Err(UciError::InvalidMove(_))
+        ));
+        assert_eq!(
+            parse_command("go ponder wtime 1000 btime 1000"),
+            Err(UciError::UnsupportedArgument("ponder"))
+        );
+        assert!(matches!(
+            parse_command("fly"),
+            Err(UciError::UnknownCommand(_))
+        ));
+    }
+
+    #[test]
+    fn uci_reject_illegal_moves() {
+        let command: Vec<&str> = "startpos moves e2e4 e2e4".split(' ').collect();
+
+        let position = rust_chess::uci::change_position(&command);
+
+        assert_eq!(
+            position.err(),
+            Some(UciError::IllegalMove("e2e4".to_string()))
+        );
+    }
+
+    #[test]
+    fn uci_parser_never_panics() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let vocabulary = [
+            "uci",
+            "isready",
+            "ucinewgame",
+            "position",
+            "startpos",
+            "fen",
+            "moves",
+            "go",
+            "depth",
+            "nodes",
+            "mate",
+            "movetime",
+            "wtime",
+            "btime",
+            "winc",
+            "binc",
+            "movestogo",
+            "infinite",
+            "ponder",
+            "searchmoves",
+            "stop",
+            "quit",
+            "e2e4",
+            "e7e5",
+            "a7a8q",
+            "e1g1",
+            "0",
+            "-1",
+            "99999999999999999999",
+            "w",
+            "b",
+            "-",
+            "KQkq",
+            "8/8/8/8/8/8/8/8",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR",
+            "P7/8/8/8/8/8/8/k6K",
+            "7k/8/8/8/8/8/8/K7",
+            "\u{e9}",
+            "",
+        ];
+        let mut rng = StdRng::seed_from_u64(42);
+
+        for _ in 0..20000 {
+            let length = rng.gen_range(0..12);
+            let line: Vec<String> = (0..length)
+                .map(|_| match rng.gen_range(0..10) {
+                    0 => (0..rng.gen_range(1..6))
+                        .map(|_| rng.gen_range(' '..='~'))
+                        .collect(),
+                    _ => vocabulary[rng.gen_range(0..vocabulary.len())].to_string(),
+                })
+                .collect();
+
+            if let Ok(UciCommand::Position { start, moves }) = parse_command(&line.join(" ")) {
+                let mut position = start.clone();
+                let _ = position.push_moves(&moves);
+            }
+        }
+    }
+
+    fn parse_moves(moves: &str) -> Vec<chess::ChessMove> {
+        moves
+            .split(' ')
+            .map(|m| chess::ChessMove::from_str(m).expect("Invalid move"))
+            .collect()
+    }
+
+    #[test]
+    fn position_push_and_pop() {
+        let mut position = Position::default();
+
+        position
+            .push_moves(&parse_moves("g1f3 g8f6 e2e4"))
+            .expect("Legal moves");
+        assert_eq!(position.halfmove_clock, 0);
+        assert_eq!(position.hash_history.len(), 4);
+
+        assert_eq!(
+            position.pop().map(|m| m.to_string()),
+            Some("e2e4".to_string())
+        );
+        assert_eq!(position.halfmove_clock, 2);
+        assert_eq!(
+            position.fen(),
+            "rnbqkb1r/pppppppp/5n2/8/8/5N2/PPPPPPPP/RNBQKB1R w KQkq - 2 2"
+        );
+
+        assert!(position.push_moves(&parse_moves("b1c3 e2e4")).is_err());
+        assert_eq!(position.len(), 2);
+    }
+
+    #[test]
+    fn position_update_takes_back_and_replays() {
+        let start = Position::default();
+        let mut position = Position::default();
+
+        position
+            .update(&start, &parse_moves("e2e4 e7e5 g1f3"))
+            .expect("Legal moves");
+        position
+            .update(&start, &parse_moves("e2e4 e7e5 f1c4 b8c6"))
+            .expect("Legal moves");
+
+        let moves: Vec<String> = position.moves().map(|m| m.to_string()).collect();
+        assert_eq!(moves, vec!["e2e4", "e7e5", "f1c4", "b8c6"]);
+
+        let before = position.clone();
+        assert!(position
+            .update(&start, &parse_moves("e2e4 e7e5 d2d4 d2d4"))
+            .is_err());
+        assert_eq!(position, before);
+    }
+
+    #[test]
+    fn position_repetition_hashes_since_last_pawn_move() {
+        let mut position = Position::default();
+
+        position
+            .push_moves(&parse_moves("g1f3 g8f6 e2e4 b8c6 f3g1"))
+            .expect("Legal moves");
+
+        assert_eq!(position.repetition_hashes(), &position.hash_history[3..]);
+    }
+
+    #[test]
+    fn position_pgn() {
+        let command: Vec<&str> = "fen 6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 3 30 moves g8h8 a1a8"
+            .split(' ')
+            .collect();
+
+        let position = rust_chess::uci::change_position(&command).expect("Invalid position");
+
+        assert_eq!(
+            position.pgn(),
+            "[SetUp \"1\"]\n[FEN \"6k1/5ppp/8/8/8/8/5PPP/R5K1 b - - 3 30\"]\n\n30... Kh8 31. Ra8# *"
+        );
+    }
+
+    fn chess960_position(fen: &str) -> Position {
+        let command = format!("fen {fen}");
+        let arguments: Vec<&str> = command.split(' ').collect();
+
+        rust_chess::uci::change_position_variant(&arguments, true).expect("Invalid position")
+    }
+
+    #[test]
+    fn chess960_perft() {
+        // Standard positions have to give the same counts in Chess960 mode
+        let kiwipete = chess960_position(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        );
+        assert_eq!(
+            (1..=3).map(|d| kiwipete.perft(d)).collect::<Vec<u64>>(),
+            vec![48, 2039, 97862]
+        );
+
+        let shredder_fen =
+            chess960_position("bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9");
+        assert_eq!(
+            (1..=3).map(|d| shredder_fen.perft(d)).collect::<Vec<u64>>(),
+            vec![21, 528, 12189]
+        );
+
+        let x_fen =
+            chess960_position("2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w KQkq - 1 9");
+        assert_eq!(
+            (1..=3).map(|d| x_fen.perft(d)).collect::<Vec<u64>>(),
+            vec![21, 807, 18002]
+        );
+    }
+
+    #[test]
+    fn chess960_king_takes_rook_castling() {
+        let mut position =
+            chess960_position("bbnqrkrn/pppppppp/8/8/8/8/PPPPPPPP/BBNQRKRN w GEge - 0 1");
+
+        // The king moves from f1 to g1 and the rook from g1 to f1
+        let castling = parse_moves("f1g1")[0];
+        assert!(position.legal_moves().contains(&castling));
+        position.push(castling).expect("Legal castling");
+
+        assert_eq!(
+            position.fen(),
+            "bbnqrkrn/pppppppp/8/8/8/8/PPPPPPPP/BBNQRRKN b kq - 1 1"
+        );
+        assert!(position.pgn().ends_with("1. O-O *"));
+    }
+
+    #[test]
+    fn chess960_castling_in_search() {
+        // Every black move is answered by castling with mate, the rook goes
+        // from h1 to f1 while the king stays on g1
+        let position = chess960_position("5k2/p3p1pB/4P1P1/1B6/8/8/8/6KR b H - 0 1");
+        let mut context = setup_test_context(position.board);
+        context.castling = position.search_castling();
+
+        let result = context.root_search(3);
+        assert_eq!(result.0, -rust_chess::config::INFINITY + 1);
+
+        // The castling rights are part of the hash
+        let without_rights = chess960_position("5k2/p3p1pB/4P1P1/1B6/8/8/8/6KR b - - 0 1");
+        assert_eq!(position.board, without_rights.board);
+        assert_ne!(position.hash(), without_rights.hash());
+    }
+
+    #[test]
+    fn xboard_parse_level() {
+        assert_eq!(
+            parse_xboard_command("level 40 5 0"),
+            Ok(XboardCommand::Level {
+                moves_per_session: 40,
+                base_ms: 300_000,
+                increment_ms: 0
+            })
+        );
+        assert_eq!(
+            parse_xboard_command("level 0 2:30 1.5"),
+            Ok(XboardCommand::Level {
+                moves_per_session: 0,
+                base_ms: 150_000,
+                increment_ms: 1500
+            })
+        );
+        assert_eq!(
+            parse_xboard_command("time -120"),
+            Ok(XboardCommand::Time(0))
+        );
+        assert_eq!(
+            parse_xboard_command("level 40 5"),
+            Err(UciError::MissingArgument("level"))
+        );
+    }
+
+    #[test]
+    fn lazy_smp_depth_skipping() {
+        use rust_chess::search::skips_depth;
+
+        assert!((1..20).all(|depth| !skips_depth(0, depth)));
+        // The first helpers search every other depth, with opposite phases
+        assert!((1..20).all(|depth| skips_depth(1, depth) != skips_depth(2, depth)));
+        assert!((1..20).any(|depth| skips_depth(3, depth)));
+    }
+
+    #[test]
+    fn lazy_smp_voting() {
+        let moves = parse_moves("e2e4 d2d4 g1f3");
+        let result = |score, chess_move, depth| AgentResult {
+            outcome: (score, chess_move),
+            depth,
+        };
+
+        // Two deeper threads agreeing outvote the principal
+        let results = [
+            result(20, moves[0], 8),
+            result(25, moves[1], 9),
+            result(24, moves[1], 9),
+            result(0, moves[2], 0),
+        ];
+        assert_eq!(select_best_result(&results).outcome.1, moves[1]);
+
+        // A proven mate always wins
+        let mate = rust_chess::config::INFINITY - 3;
+        let results = [result(20, moves[0], 8), result(mate, moves[2], 4)];
+        assert_eq!(select_best_result(&results).outcome, (mate, moves[2]));
+
+        // Threads without a completed iteration do not vote
+        let results = [result(0, moves[0], 0), result(-50, moves[1], 1)];
+        assert_eq!(select_best_result(&results).outcome.1, moves[1]);
+    }
+
+    #[test]
+    fn root_search_without_legal_moves() {
+        let mated =
+            chess::Board::from_str("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").expect("Invalid position");
+        let stalemate =
+            chess::Board::from_str("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").expect("Invalid position");
+
+        let (score, best_move) = setup_test_context(mated).root_search(4);
+        assert_eq!(rust_chess::printer::format_score(score), "mate 0");
+        assert_eq!(rust_chess::printer::format_move(best_move), "0000");
+
+        let (score, best_move) = setup_test_context(stalemate).root_search(4);
+        assert_eq!(score, rust_chess::config::DRAW);
+        assert_eq!(rust_chess::printer::format_move(best_move), "0000");
+    }
+
+    #[test]
+    fn root_search_stopped_before_first_iteration() {
+        let mut context = setup_test_context(chess::Board::default());
+        context
+            .stop_flag
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let (score, best_move) = context.root_search(4);
+        assert_eq!(context.completed_depth, 0);
+        assert_eq!(rust_chess::printer::format_score(score), "cp 0");
+        assert!(chess::Board::default().legal(best_move));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn search_stats_are_collected() {
+        let board = chess::Board::from_str(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+        )
+        .expect("Invalid position");
+        let mut context = setup_test_context(board);
+        context.root_search(4);
+
+        let stats = context.stats.clone();
+        assert!(stats.nodes > 0 && stats.qnodes > 0);
+        assert!(stats.tt_probes > 0 && stats.tt_hits.iter().sum::<u64>() <= stats.tt_probes);
+        assert!(stats.first_move_cutoffs <= stats.beta_cutoffs);
+        assert_eq!(stats.stage_cutoffs.iter().sum::<u64>(), stats.beta_cutoffs);
+        assert_eq!(stats.nodes + stats.qnodes, context.nodes);
+
+        // A new search starts counting from zero
+        context.reset_search(board);
+        assert_eq!(context.stats, Default::default());
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn search_stats_of_all_threads_are_merged() {
+        use rust_chess::printer::{set_debug, OutputEvent};
+        use rust_chess::threading::ThreadPool;
+
+        let mut pool = ThreadPool::new(3, 1 << 12);
+        let mut stats_lines = |debug: bool| {
+            set_debug(debug);
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let group = pool
+                .start(&Position::default(), sender, 4, None, None, &[])
+                .expect("Search did not start");
+            group.await_principal().expect("Search failed");
+            receiver
+                .try_iter()
+                .filter(|event| match event {
+                    OutputEvent::Raw(line) => line.starts_with("info string stats "),
+                    _ => false,
+                })
+                .count()
+        };
+
+        // One line for the whole group, with the nodes of every thread, and
+        // only in debug mode
+        assert_eq!(stats_lines(false), 0);
+        assert_eq!(stats_lines(true), 1);
+        set_debug(false);
+        pool.shutdown();
+
+        let mut context = setup_test_context(chess::Board::default());
+        context.root_search(4);
+        let mut merged = context.stats.clone();
+        merged.merge(&context.stats);
+        assert_eq!(merged.nodes, 2 * context.stats.nodes);
+        assert_eq!(merged.stage_cutoffs[0], 2 * context.stats.stage_cutoffs[0]);
+    }
+
+    #[cfg(feature = "tree")]
+    #[test]
+    fn search_tree_dump() {
+        use rust_chess::tree::{record_search_tree, TreeFormat};
+
+        let position = rust_chess::uci::change_position(&["startpos", "moves", "e2e4", "e7e5"])
+            .expect("Valid position");
+        let (outcome, tree) = record_search_tree(&position, 3, 1);
+
+        // Only the root and its children are recorded
+        let root = &tree.nodes[0];
+        assert_eq!(root.children.len(), position.legal_moves().len());
+        assert_eq!(tree.nodes.len(), root.children.len() + 1);
+        assert_eq!(root.score, Some(outcome.0));
+        assert!(tree.nodes[1..].iter().all(|node| node.children.is_empty()));
+
+        assert!(tree.export(TreeFormat::Dot).starts_with("digraph search {"));
+        let json = tree.export(TreeFormat::Json);
+        assert!(json.starts_with("{\"move\":null,"));
+        assert_eq!(json.matches("\"move\":").count(), tree.nodes.len());
+
+        assert_eq!(
+            parse_command("tree depth 2 plies 3 json"),
+            Ok(UciCommand::Tree {
+                depth: 2,
+                plies: 3,
+                format: TreeFormat::Json
+            })
+        );
+    }
+
+    // Every position of the corpus, its color flipped and its left-right
+    // mirrored version evaluate the same for the side to move
+    #[test]
+    fn evaluation_symmetry_harness() {
+        use rust_chess::epd::epd_board;
+        use rust_chess::eval::evaluate;
+        use rust_chess::test_utils::{flip_colors, mirror_files};
+
+        let corpus = include_str!("data/symmetry.epd");
+        let mut positions = 0;
+
+        for line in corpus.lines().filter(|line| !line.trim().is_empty()) {
+            let board = epd_board(line).unwrap_or_else(|| panic!("Invalid EPD: {line}"));
+            let score = evaluate(&board);
+            positions += 1;
+
+            let flipped = flip_colors(&board);
+            assert_eq!(score, evaluate(&flipped), "color flip of {line}");
+
+            let mirrored = mirror_files(&board);
+            assert_eq!(score, evaluate(&mirrored), "mirror of {line}");
+            assert_eq!(
+                score,
+                evaluate(&flip_colors(&mirrored)),
+                "flipped mirror of {line}"
+            );
+        }
+        assert!(positions >= 20);
+
+        // The start position is about equal
+        let start = epd_board(corpus.lines().next().unwrap_or_default()).expect("Valid EPD");
+        assert!(evaluate(&start).abs() < 50);
+    }
+
+    #[test]
+    fn epd_board_rejects_invalid_positions() {
+        use rust_chess::epd::epd_board;
+
+        assert!(epd_board("4k3/8/8/8/8/8/8/4K3 w - - c9 \"1-0\";").is_some());
+        assert_eq!(epd_board("8/8/8/8/8/8/8/8 w - - c9 \"1-0\";"), None);
+        assert_eq!(epd_board("4k3/8/8/8/8/8/8/P3K3 w - -"), None);
+        assert_eq!(epd_board("4k3/8/8/8 w -"), None);
+    }
+
+    #[test]
+    fn pawn_structure() {
+        use rust_chess::params::DEFAULT_PARAMS;
+        use rust_chess::pawns::{evaluate_pawns, is_passed, pawn_hash, PawnTable};
+        use rust_chess::test_utils::flip_colors;
+
+        // White: passed d5 and h2, doubled b-pawns. Black: passed e4 and f7
+        let board = chess::Board::from_str("4k3/p4p2/8/1P1P4/4p3/1P6/7P/4K3 w - - 0 1")
+            .expect("Invalid position");
+        let white = chess::Color::White;
+        assert!(is_passed(&board, white, chess::Square::D5));
+        assert!(!is_passed(&board, white, chess::Square::B3));
+        assert!(!is_passed(&board, white, chess::Square::B5));
+
+        let squares = |squares: &[chess::Square]| {
+            squares.iter().fold(chess::EMPTY, |bitboard, square| {
+                bitboard | chess::BitBoard::from_square(*square)
+            })
+        };
+        let entry = evaluate_pawns(&board, &DEFAULT_PARAMS);
+        assert_eq!(
+            entry.passed[0],
+            squares(&[chess::Square::D5, chess::Square::H2])
+        );
+        assert_eq!(
+            entry.passed[1],
+            squares(&[chess::Square::E4, chess::Square::F7])
+        );
+        assert_eq!(
+            evaluate_pawns(&flip_colors(&board), &DEFAULT_PARAMS).score,
+            -entry.score
+        );
+
+        // The key only depends on the pawns and the table returns the same
+        // entry as the direct evaluation
+        let king_move = board.make_move_new(chess::ChessMove::new(
+            chess::Square::E1,
+            chess::Square::F1,
+            None,
+        ));
+        assert_eq!(pawn_hash(&board), pawn_hash(&king_move));
+        assert_ne!(pawn_hash(&board), pawn_hash(&flip_colors(&board)));
+
+        let mut table = PawnTable::new();
+        for _ in 0..2 {
+            let cached = table.probe(&board, &DEFAULT_PARAMS);
+            assert_eq!(cached.score, entry.score);
+            assert_eq!(cached.passed, entry.passed);
+        }
+
+        let mut evaluator = rust_chess::eval::Evaluator::new();
+        assert_eq!(
+            evaluator.evaluate(&board),
+            rust_chess::eval::evaluate(&board)
+        );
+    }
+
+    #[test]
+    fn pawn_shield_mirrored_for_black() {
+        use rust_chess::eval::evaluate_with_params;
+        use rust_chess::test_utils::flip_colors;
+
+        // Only a king on g1, or g8 for black, is rewarded for its shield
+        let mut params = rust_chess::params::DEFAULT_PARAMS;
+        params.pawn_shield = [[0; 8]; 8];
+        params.pawn_shield[0][6] = 50;
+
+        // The pieces keep the shield from being scaled away, it is middlegame only
+        let board = chess::Board::from_str("r2q1rk1/5ppp/8/8/8/8/5PPP/R2Q1RK1 w - - 0 1")
+            .expect("Invalid position");
+        assert_eq!(
+            evaluate_with_params(&board, &params),
+            evaluate_with_params(&flip_colors(&board), &params)
+        );
+    }
+
+    #[test]
+    fn king_safety() {
+        use rust_chess::eval::king_safety;
+        let white = chess::Color::White;
+        let safety = |fen: &str| {
+            let board = chess::Board::from_str(fen).expect("Invalid position");
+            king_safety(&board, white, &rust_chess::params::DEFAULT_PARAMS)
+        };
+
+        let sheltered = safety("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1");
+        assert_eq!(sheltered, rust_chess::eval::Score::new(0, 0));
+
+        // A queen and a knight attacking the king zone
+        let attacked = safety("6k1/5ppp/8/8/6n1/7q/5PPP/6K1 w - - 0 1");
+        // The g-file is open, the h-file half-open with a storming pawn
+        let exposed = safety("6k1/5pp1/8/8/8/7p/5P2/6K1 w - - 0 1");
+        // A single attacker adds no danger
+        let single = safety("6k1/5ppp/8/8/6n1/8/5PPP/6K1 w - - 0 1");
+
+        assert!(attacked.mg < sheltered.mg && attacked.mg < attacked.eg);
+        assert!(exposed.mg < sheltered.mg);
+        assert_eq!(single, sheltered);
+    }
+
+    #[test]
+    fn piece_placement() {
+        use rust_chess::eval::evaluate_piece_placement;
+        let placement = |fen: &str, color: chess::Color| {
+            let board = chess::Board::from_str(fen).expect("Invalid position");
+            evaluate_piece_placement(&board, color, &rust_chess::params::DEFAULT_PARAMS)
+        };
+        let white = chess::Color::White;
+
+        // Bishop pair against a single bishop on the same squares
+        let pair = placement("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", white);
+        let single = placement("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", white);
+        assert!(pair.mg > single.mg && pair.eg > single.eg);
+
+        // Open beats semi-open beats closed for a rook
+        let open = placement("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", white);
+        let semi_open = placement("4k3/p7/8/8/8/8/8/R3K3 w - - 0 1", white);
+        let closed = placement("4k3/p7/8/8/8/8/P7/R3K3 w - - 0 1", white);
+        assert!(open.mg > semi_open.mg && semi_open.mg > closed.mg);
+
+        // A rook on the seventh with the enemy king on the eighth
+        let seventh = placement("4k3/R7/8/8/8/8/8/4K3 w - - 0 1", white);
+        let sixth = placement("4k3/8/R7/8/8/8/8/4K3 w - - 0 1", white);
+        assert!(seventh.eg > sixth.eg);
+
+        // A knight on d5 supported by e4 with no black pawn to chase it
+        let outpost = placement("4k3/8/8/3N4/4P3/8/8/4K3 w - - 0 1", white);
+        let chased = placement("4k3/2p5/8/3N4/4P3/8/8/4K3 w - - 0 1", white);
+        assert!(outpost.mg > chased.mg);
+
+        // A rook locked in by the king and a bishop cut off on a7
+        let trapped = placement("4k3/8/8/8/8/8/8/5KR1 w - - 0 1", white);
+        let free = placement("4k3/8/8/8/8/8/8/4K1R1 w - - 0 1", white);
+        assert!(trapped.mg < free.mg);
+        let bishop = placement("4k3/B7/1p6/8/8/8/8/4K3 w - - 0 1", white);
+        let escaped = placement("4k3/B7/8/8/8/8/8/4K3 w - - 0 1", white);
+        assert!(bishop.mg < escaped.mg);
+    }
+
+    #[test]
+    fn threats() {
+        use rust_chess::eval::{evaluate_threats, AttackMaps};
+        use rust_chess::params::DEFAULT_PARAMS;
+        let threats = |fen: &str| {
+            let board = chess::Board::from_str(fen).expect("Invalid position");
+            let attacks = AttackMaps::new(&board, &DEFAULT_PARAMS);
+            evaluate_threats(&board, chess::Color::White, &attacks, &DEFAULT_PARAMS)
+        };
+        let none = threats("4k3/8/8/3n4/8/8/8/4K3 w - - 0 1");
+        assert_eq!(none, rust_chess::eval::Score::new(0, 0));
+
+        // A pawn attacking the knight beats a pawn push that would attack it
+        let attacked = threats("4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1");
+        let push = threats("4k3/8/8/3n4/8/4P3/8/4K3 w - - 0 1");
+        assert!(attacked.mg > push.mg && push.mg > none.mg);
+
+        // The knight is only hanging while no pawn defends it
+        let hanging = threats("4k3/8/8/3n4/8/8/8/3RK3 w - - 0 1");
+        let defended = threats("4k3/8/4p3/3n4/8/8/8/3RK3 w - - 0 1");
+        assert!(hanging.mg > defended.mg);
+        assert_eq!(defended, none);
+    }
+
+    #[test]
+    fn eval_params() {
+        use rust_chess::eval::{evaluate, evaluate_with_params};
+        use rust_chess::params::EvalParams;
+        let board =
+            chess::Board::from_str("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").expect("Invalid position");
+
+        let mut params = EvalParams::default();
+        assert_eq!(evaluate_with_params(&board, &params), evaluate(&board));
+        params.piece_values[chess::Piece::Queen.to_index()] +=
+            rust_chess::eval::Score::new(100, 100);
+        assert_eq!(
+            evaluate_with_params(&board, &params),
+            evaluate(&board) + 100
+        );
+    }
+
+    #[cfg(feature = "tune")]
+    #[test]
+    fn eval_params_from_json_and_options() {
+        use rust_chess::options::EngineOptions;
+        use rust_chess::params::EvalParams;
+
+        let params = EvalParams::default();
+        let json = params.to_json();
+        assert_eq!(EvalParams::from_json(&json).unwrap(), params);
+        assert!(EvalParams::from_json("{\"pin_value\": 3}").is_err());
+
+        let mut changed = params.clone();
+        changed.set_scalar("pin_value_eg", 7).unwrap();
+        changed.set_scalar("king_danger_max", 400).unwrap();
+        assert_eq!(changed.pin_value, rust_chess::eval::Score::new(5, 7));
+        assert_eq!(changed.king_danger_max, 400);
+        assert!(changed.set_scalar("pin_value", 1).is_err());
+        assert!(changed.set_scalar("psqt_mg", 1).is_err());
+
+        // Every listed option is accepted
+        let mut options = EngineOptions::default();
+        for line in options.uci_option_lines() {
+            let name = line.split_whitespace().nth(2).unwrap().to_string();
+            if name.starts_with("Eval_") {
+                options.set_option(&name, Some("1")).unwrap();
+            }
+        }
+        assert_eq!(
+            options.eval_params.pin_value,
+            rust_chess::eval::Score::new(1, 1)
+        );
+        options.set_option("EvalParams", Some("<empty>")).unwrap();
+        assert_eq!(*options.eval_params, params);
+        assert!(options
+            .set_option("EvalParams", Some("missing.json"))
+            .is_err());
+    }
+
+    #[cfg(feature = "tune")]
+    #[test]
+    fn texel_tuner() {
+        use rust_chess::params::EvalParams;
+        use rust_chess::tuner::{flatten, parse_labelled_position, unflatten, Tuner};
+
+        let result = |line: &str| parse_labelled_position(line).map(|position| position.result);
+        assert_eq!(result("8/8/8/8/8/8/8/K1k5 w - - c9 \"1-0\";"), Some(1.0));
+        assert_eq!(result("8/8/8/8/8/8/8/K1k5 b - - 0 1 [0.5]"), Some(0.5));
+        assert_eq!(result("8/8/8/8/8/8/8/K1k5 b - - 0 1 0-1"), Some(0.0));
+        assert_eq!(result("8/8/8/8/8/8/8/K1k5 w - - 0 1"), None);
+        assert_eq!(result("4k3/8/8/8/8/8/8/8 w - - c9 \"1-0\";"), None);
+
+        let params = EvalParams::default();
+        let mut values = flatten(&params);
+        assert_eq!(unflatten(&params, &values), params);
+        values[0] += 1;
+        let changed = unflatten(&params, &values);
+        assert_ne!(changed, params);
+        assert_eq!(flatten(&changed), values);
+
+        // White is a queen up and won, the error shrinks with a fitted scaling
+        let positions = [
+            "3qk3/8/8/8/8/8/8/3QK2Q w - - c9 \"1-0\";",
+            "3qk3/8/8/8/8/8/8/Q2QK3 b - - c9 \"1-0\";",
+            "3qk3/8/8/8/8/8/8/3QK3 w - - c9 \"1/2-1/2\";",
+        ];
+        let mut tuner = Tuner::new(
+            positions
+                .iter()
+                .filter_map(|line| parse_labelled_position(line))
+                .collect(),
+            2,
+        );
+        assert_eq!(tuner.len(), 3);
+        let unscaled = tuner.error(&params);
+        tuner.fit_scaling(&params);
+        assert!(tuner.error(&params) < unscaled);
+    }
+
+    // The file of a network with random weights in the documented format, the
+    // weights are small enough that the accumulators do not overflow
+    pub fn random_network_bytes(l1: usize, l2: usize, seed: u64) -> Vec<u8> {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut bytes = b"RCNN".to_vec();
+        for value in [1, 0, l1 as u32, l2 as u32, 16] {
+            bytes.extend(value.to_le_bytes());
+        }
+        for _ in 0..l1 {
+            bytes.extend(rng.gen_range(0i16..64).to_le_bytes());
+        }
+        for _ in 0..rust_chess::nnue::FEATURES * l1 {
+            bytes.extend(rng.gen_range(-32i16..32).to_le_bytes());
+        }
+        for _ in 0..l2 {
+            bytes.extend(rng.gen_range(-2000i32..2000).to_le_bytes());
+        }
+        bytes.extend((0..l2 * 2 * l1).map(|_| rng.gen_range(-64i8..64) as u8));
+        bytes.extend(rng.gen_range(-2000i32..2000).to_le_bytes());
+        bytes.extend((0..l2).map(|_| rng.gen_range(-64i8..64) as u8));
+        bytes
+    }
+
+    #[test]
+    fn nnue_file_format() {
+        use rust_chess::nnue::Network;
+
+        let bytes = random_network_bytes(16, 32, 1);
+        let network = Network::from_bytes(&bytes).expect("Valid network");
+        assert_eq!(network.to_bytes(), bytes);
+
+        assert!(Network::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Network::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(Network::from_bytes(&wrong_magic).is_err());
+        // The accumulator size has to be a multiple of 16
+        let mut wrong_size = bytes.clone();
+        wrong_size[12] = 15;
+        assert!(Network::from_bytes(&wrong_size).is_err());
+    }
+
+    #[test]
+    fn nnue_incremental_updates() {
+        use rust_chess::eval::Evaluator;
+        use rust_chess::nnue::{Network, NnueEvaluator};
+        use std::sync::Arc;
+
+        let network = Network::from_bytes(&random_network_bytes(32, 32, 2)).expect("Valid network");
+        let mut scalar_network = network.clone();
+        scalar_network.set_simd(false);
+        let network = Arc::new(network);
+        let fresh = |board: &chess::Board| NnueEvaluator::new(network.clone()).evaluate(board);
+
+        // Every kind of move within two plies: captures, en passant, castling
+        // on both sides and promotions with and without a capture
+        let board = chess::Board::from_str("r3k2r/1P4P1/8/3pP3/8/8/1p4p1/R3K2R w KQkq d6 0 1")
+            .expect("Invalid position");
+        let mut evaluator = Evaluator::new();
+        evaluator.set_network(Some(network.clone()));
+        let mut scalar = NnueEvaluator::new(Arc::new(scalar_network));
+        let root_score = evaluator.evaluate(&board);
+        assert_eq!(root_score, fresh(&board));
+
+        for first in chess::MoveGen::new_legal(&board) {
+            let child = board.make_move_new(first);
+            evaluator.push(&board, first, &child);
+            scalar.push(&board, first, &child);
+            assert_eq!(evaluator.evaluate(&child), fresh(&child), "after {first}");
+            assert_eq!(scalar.evaluate(&child), fresh(&child), "after {first}");
+
+            for second in chess::MoveGen::new_legal(&child) {
+                let grandchild = child.make_move_new(second);
+                evaluator.push(&child, second, &grandchild);
+                let score = evaluator.evaluate(&grandchild);
+                assert_eq!(score, fresh(&grandchild), "after {first} {second}");
+                evaluator.pop();
+            }
+            evaluator.pop();
+            scalar.pop();
+        }
+        assert_eq!(evaluator.evaluate(&board), root_score);
+
+        evaluator.set_network(None);
+        assert_eq!(
+            evaluator.evaluate(&board),
+            rust_chess::eval::evaluate(&board)
+        );
+    }
+
+    #[test]
+    fn nnue_options() {
+        use rust_chess::options::EngineOptions;
+
+        let path = std::env::temp_dir().join(format!("nnue_options_{}.nnue", std::process::id()));
+        std::fs::write(&path, random_network_bytes(16, 32, 3)).expect("Writable temp dir");
+        let path = path.to_str().expect("Valid path").to_string();
+
+        let mut options = EngineOptions::default();
+        assert!(options
+            .uci_option_lines()
+            .contains(&"option name UseNNUE type check default false".to_string()));
+        options.set_option("EvalFile", Some(&path)).unwrap();
+        assert!(options.network.is_some());
+        assert!(options.active_network().is_none());
+        options.set_option("UseNNUE", Some("true")).unwrap();
+        assert!(options.active_network().is_some());
+        assert!(!options.missing_network());
+        options.set_option("EvalFile", Some("<empty>")).unwrap();
+        assert!(options.active_network().is_none());
+        assert!(options.missing_network());
+        assert!(options
+            .set_option("EvalFile", Some("missing.nnue"))
+            .is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn training_data_records() {
+        use rust_chess::datagen::{GameResult, TrainingPosition};
+
+        for (fen, score, ply, result) in [
+            (
+                "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 1",
+                35,
+                41,
+                GameResult::WhiteWin,
+            ),
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                0,
+                0,
+                GameResult::Draw,
+            ),
+            (
+                "8/8/4k3/8/8/8/2p5/K7 b - - 0 1",
+                -1200,
+                301,
+                GameResult::BlackWin,
+            ),
+        ] {
+            let position = TrainingPosition {
+                board: chess::Board::from_str(fen).expect("Invalid position"),
+                score,
+                ply,
+                result,
+            };
+            assert_eq!(
+                TrainingPosition::unpack(&position.pack()),
+                Some(position.clone())
+            );
+            assert!(position
+                .to_text()
+                .ends_with(&format!(" | {score} | {ply} | {}", result.to_text())));
+        }
+        assert_eq!(TrainingPosition::unpack(&[0xff; 32]), None);
+        // An empty board and a lone white pawn on a1, without kings
+        assert_eq!(TrainingPosition::unpack(&[0; 32]), None);
+        let mut pawn_on_a1 = [0; 32];
+        pawn_on_a1[0] = 1;
+        pawn_on_a1[28] = 1;
+        assert_eq!(TrainingPosition::unpack(&pawn_on_a1), None);
+    }
+
+    #[test]
+    fn training_data_generator() {
+        use rand::SeedableRng;
+        use rust_chess::datagen::{Generator, GeneratorSettings};
+
+        let mut generator = Generator::new(GeneratorSettings {
+            depth: 2,
+            max_plies: 40,
+            ..GeneratorSettings::default()
+        });
+        let positions = generator.play_game(&mut rand::rngs::StdRng::seed_from_u64(1));
+
+        assert!(!positions.is_empty());
+        assert!(positions.iter().all(|position| position.ply >= 8
+            && position.ply < 40
+            && *position.board.checkers() == chess::EMPTY
+            && position.result == positions[0].result));
     }
 }